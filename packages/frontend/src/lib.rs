@@ -1,6 +1,6 @@
 use arpy::ConcurrentRpcClient;
 use arpy_reqwasm::websocket;
-use codastraea_server_api::{NodeVecDiff, WatchCallTree};
+use codastraea_server_api::{ListRuns, NodeVecDiff, RunId, WatchCallTree};
 use futures::{stream, Stream, StreamExt};
 use gloo_net::websocket::futures::WebSocket;
 
@@ -18,6 +18,14 @@ impl Default for ServerConnection {
 }
 
 impl ServerConnection {
+    pub async fn runs(&self) -> Vec<RunId> {
+        // TODO: Error handling
+        let call = self.ws.begin_call(ListRuns).await;
+        call.expect("TODO: Error handling")
+            .await
+            .expect("TODO: Error handling")
+    }
+
     pub async fn watch(
         &self,
         watch_call_tree: WatchCallTree,
@@ -1,4 +1,4 @@
-use arpy::{FnSubscription, MsgId};
+use arpy::{FnRemote, FnSubscription, MsgId};
 use serde::{Deserialize, Serialize};
 use slotmap::new_key_type;
 
@@ -10,24 +10,91 @@ pub enum CallTreeNodeId {
 
 new_key_type! {pub struct CallTreeChildNodeId;}
 
+new_key_type! {
+    /// Identifies a workflow run on the server.
+    pub struct RunId;
+}
+
+/// Start a run of the workflow `module::name`.
+#[derive(MsgId, Serialize, Deserialize, Debug)]
+pub struct StartRun {
+    pub module: String,
+    pub name: String,
+}
+
+impl FnRemote for StartRun {
+    type Output = Result<RunId, String>;
+}
+
+/// Every run on the server.
+#[derive(MsgId, Serialize, Deserialize, Debug)]
+pub struct ListRuns;
+
+impl FnRemote for ListRuns {
+    type Output = Vec<RunId>;
+}
+
+/// Where a run is in its lifecycle.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum RunState {
+    /// Started, but not run yet.
+    Queued,
+    /// Running in the guest.
+    Running,
+    /// Stopped at a checkpoint, with an optional label from the guest.
+    Paused { label: Option<String> },
+    /// Finished, with an optional return value from the guest.
+    Completed { return_value: Option<String> },
+    /// The guest trapped, exceeded a limit, or otherwise failed, with `error`.
+    Failed { error: String },
+}
+
+impl RunState {
+    /// Has the run completed or failed?
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Completed { .. } | Self::Failed { .. })
+    }
+}
+
+/// Watch the state of a run.
+///
+/// The current state is sent first, then each change, until the run finishes.
+#[derive(MsgId, Serialize, Deserialize, Debug)]
+pub struct WatchRun {
+    pub run_id: RunId,
+}
+
+impl FnSubscription for WatchRun {
+    type InitialReply = ();
+    type Item = RunState;
+    type Update = ();
+}
+
 #[derive(MsgId, Serialize, Deserialize, Debug)]
 pub struct WatchCallTree {
+    run_id: RunId,
     node_id: CallTreeNodeId,
 }
 
 impl WatchCallTree {
-    pub fn root() -> Self {
+    pub fn root(run_id: RunId) -> Self {
         Self {
+            run_id,
             node_id: CallTreeNodeId::Root,
         }
     }
 
-    pub fn node(node_id: CallTreeChildNodeId) -> Self {
+    pub fn node(run_id: RunId, node_id: CallTreeChildNodeId) -> Self {
         Self {
+            run_id,
             node_id: CallTreeNodeId::Child(node_id),
         }
     }
 
+    pub fn run_id(&self) -> RunId {
+        self.run_id
+    }
+
     pub fn id(&self) -> CallTreeNodeId {
         self.node_id
     }
@@ -44,6 +111,8 @@ pub struct NewNode {
     pub id: CallTreeChildNodeId,
     pub typ: NodeType,
     pub status: NodeStatus,
+    /// Why the node failed, if its status is [`NodeStatus::Failed`].
+    pub error: Option<String>,
     pub has_children: bool,
 }
 
@@ -92,8 +161,18 @@ impl NodeType {
 pub enum NodeVecDiff {
     Replace(Vec<NewNode>),
     Push(NewNode),
-    SetStatus { index: usize, status: NodeStatus },
-    SetHasChildren { index: usize },
+    SetStatus {
+        index: usize,
+        status: NodeStatus,
+    },
+    SetHasChildren {
+        index: usize,
+    },
+    /// The node at `index` was running when the guest failed with `error`.
+    SetFailed {
+        index: usize,
+        error: String,
+    },
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
@@ -101,6 +180,7 @@ pub enum NodeStatus {
     NotRun,
     Running,
     Complete,
+    Failed,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -1,45 +1,79 @@
-use std::{path::PathBuf, thread};
+use std::{fs, path::PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use arpy_axum::RpcRoute;
 use arpy_server::WebSocketRouter;
 use axum::{Router, Server};
 use clap::Parser;
-use codastraea_server_api::WatchCallTree;
-use codastraea_wasm_host::runtime::Container;
-use futures::stream::BoxStream;
+use codastraea_server_api::{ListRuns, RunId, StartRun, WatchCallTree, WatchRun};
+use codastraea_wasm_host::{run::RunManager, runtime::ContainerBuilder};
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// The WASM module. This can be text or binary format
     file: PathBuf,
+    /// A workflow to run on startup, as `module::name`. More can be started
+    /// with the `StartRun` API call.
+    #[arg(long = "workflow")]
+    workflows: Vec<String>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let wat_file = Args::parse().file;
-    let mut container = Container::from_file(&wat_file)?;
-
-    container.register_workflows()?;
-    container.init_workflow("codastraea_test_workflow", "counter")?;
-    let node_store = container.node_store();
-
-    thread::spawn({
-        move || {
-            // TODO: Handle errors
-            while container.run().expect("TODO") {
-                println!("Checkpoint");
-            }
-        }
-    });
+    let args = Args::parse();
+    let wat_file = args.file;
+    let module = fs::read(&wat_file).with_context(|| format!("Opening file {wat_file:?}"))?;
+    let runs = RunManager::new_async(ContainerBuilder::new(), &module)?;
 
-    let ws = WebSocketRouter::new().handle_subscription({
-        move |_updates: BoxStream<'static, ()>, watch: WatchCallTree| {
-            let updates = node_store.watch(watch.id());
-            ((), updates)
-        }
-    });
+    for workflow in &args.workflows {
+        let (module, name) = workflow
+            .split_once("::")
+            .with_context(|| format!("Expected `module::name`, not {workflow:?}"))?;
+        start(&runs, module, name).await?;
+    }
+
+    let ws = WebSocketRouter::new()
+        .handle({
+            let runs = runs.clone();
+            move |StartRun { module, name }: StartRun| {
+                let runs = runs.clone();
+                async move {
+                    start(&runs, &module, &name)
+                        .await
+                        .map_err(|e| format!("{e:#}"))
+                }
+            }
+        })
+        .handle({
+            let runs = runs.clone();
+            move |ListRuns| {
+                let run_ids: Vec<RunId> = runs.runs().into_iter().map(|(id, _)| id).collect();
+                async move { run_ids }
+            }
+        })
+        .handle_subscription({
+            let runs = runs.clone();
+            move |_updates: BoxStream<'static, ()>, WatchRun { run_id }: WatchRun| {
+                let states = runs
+                    .watch(run_id)
+                    .unwrap_or_else(|| stream::empty().boxed());
+                ((), states)
+            }
+        })
+        .handle_subscription({
+            move |_updates: BoxStream<'static, ()>, watch: WatchCallTree| {
+                let updates = match runs.node_store(watch.run_id()) {
+                    Some(node_store) => node_store.watch(watch.id()).boxed(),
+                    None => stream::empty().boxed(),
+                };
+                ((), updates)
+            }
+        });
 
     let app = Router::new().ws_rpc_route("/api", ws, 10000);
     Server::bind(&"0.0.0.0:9090".parse().unwrap())
@@ -49,3 +83,20 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Start a run of `module::name`, and run it to completion on a tokio task.
+async fn start(runs: &RunManager, module: &str, name: &str) -> Result<RunId> {
+    let run_id = runs.start_async(module, name).await?;
+
+    tokio::spawn({
+        let runs = runs.clone();
+        async move {
+            match runs.run_async(run_id).await {
+                Ok(state) => println!("Run {run_id:?} finished: {state:?}"),
+                Err(e) => println!("Run {run_id:?} failed: {e:#}"),
+            }
+        }
+    });
+
+    Ok(run_id)
+}
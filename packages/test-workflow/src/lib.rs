@@ -1,4 +1,4 @@
-use codastraea_wasm_guest::{checkpoint, log, workflow};
+use codastraea_wasm_guest::{labelled_checkpoint, log, workflow};
 
 fn condition() -> bool {
     log("condition");
@@ -6,7 +6,7 @@ fn condition() -> bool {
 }
 
 #[workflow]
-async fn counter() {
+async fn counter() -> usize {
     if condition() {
         if !condition() {
             log("false");
@@ -22,8 +22,10 @@ async fn counter() {
     for i in 0..10 {
         log(format!("{i}"));
         child_fn().await;
-        checkpoint().await;
+        labelled_checkpoint(format!("iteration {i}")).await;
     }
+
+    10
 }
 
 #[workflow]
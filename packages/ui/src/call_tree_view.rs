@@ -2,7 +2,7 @@ use std::{cell::OnceCell, pin::pin, rc::Rc};
 
 use codastraea_frontend::ServerConnection;
 use codastraea_server_api::{
-    CallTreeChildNodeId, NewNode, NodeStatus, NodeType, NodeVecDiff, SrcSpan, WatchCallTree,
+    CallTreeChildNodeId, NewNode, NodeStatus, NodeType, NodeVecDiff, RunId, SrcSpan, WatchCallTree,
 };
 use derive_more::Into;
 use futures::StreamExt;
@@ -35,6 +35,7 @@ struct NodeData {
     id: CallTreeChildNodeId,
     typ: NodeType,
     status: Mutable<NodeStatus>,
+    error: Mutable<Option<String>>,
     has_children: Mutable<bool>,
 }
 
@@ -44,26 +45,37 @@ impl NodeData {
             id: value.id,
             typ: value.typ,
             status: Mutable::new(value.status),
+            error: Mutable::new(value.error),
             has_children: Mutable::new(value.has_children),
         })
     }
 }
 
 impl CallTreeView {
-    pub fn new(server: ServerConnection, actions: impl CallTreeActions) -> Self {
+    pub fn new(server: ServerConnection, run_id: RunId, actions: impl CallTreeActions) -> Self {
         let children = MutableVec::<Rc<NodeData>>::new();
-        update_node_children(server.clone(), WatchCallTree::root(), children.clone());
+        update_node_children(
+            server.clone(),
+            WatchCallTree::root(run_id),
+            children.clone(),
+        );
 
         Self(
             tree::container()
                 .compact_size(true)
-                .item_children_signal(Self::node_children(server.clone(), actions, &children))
+                .item_children_signal(Self::node_children(
+                    server.clone(),
+                    run_id,
+                    actions,
+                    &children,
+                ))
                 .into(),
         )
     }
 
     fn node(
         server: &ServerConnection,
+        run_id: RunId,
         data: &Rc<NodeData>,
         actions: impl CallTreeActions,
     ) -> tree::CustomItem {
@@ -71,27 +83,33 @@ impl CallTreeView {
 
         let once = OnceCell::new();
         let children = MutableVec::<Rc<NodeData>>::new();
-        node.item_children_signal(Self::node_children(server.clone(), actions, &children))
-            .has_children(Sig(data.has_children.signal()))
-            .on_toggle({
-                let node_id = data.id;
-                clone!(server);
-                move |expanded| {
-                    if expanded == Toggle::Expand {
-                        once.get_or_init(|| {
-                            update_node_children(
-                                server.clone(),
-                                WatchCallTree::node(node_id),
-                                children.clone(),
-                            );
-                        });
-                    }
+        node.item_children_signal(Self::node_children(
+            server.clone(),
+            run_id,
+            actions,
+            &children,
+        ))
+        .has_children(Sig(data.has_children.signal()))
+        .on_toggle({
+            let node_id = data.id;
+            clone!(server);
+            move |expanded| {
+                if expanded == Toggle::Expand {
+                    once.get_or_init(|| {
+                        update_node_children(
+                            server.clone(),
+                            WatchCallTree::node(run_id, node_id),
+                            children.clone(),
+                        );
+                    });
                 }
-            })
+            }
+        })
     }
 
     fn node_children(
         server: ServerConnection,
+        run_id: RunId,
         actions: impl CallTreeActions,
         children: &MutableVec<Rc<NodeData>>,
     ) -> futures_signals::signal_vec::Map<
@@ -100,7 +118,7 @@ impl CallTreeView {
     > {
         children
             .signal_vec_cloned()
-            .map(move |c| Self::node(&server, &c, actions.clone()))
+            .map(move |c| Self::node(&server, run_id, &c, actions.clone()))
     }
 }
 
@@ -125,6 +143,12 @@ fn update_node_children(
                     .push_cloned(NodeData::from_update(update)),
                 Diff::SetStatus { index, status } => children.lock_ref()[index].status.set(status),
                 Diff::SetHasChildren { index } => children.lock_ref()[index].has_children.set(true),
+                Diff::SetFailed { index, error } => {
+                    let children = children.lock_ref();
+                    let node = &children[index];
+                    node.error.set(Some(error));
+                    node.status.set(NodeStatus::Failed);
+                }
             }
         }
     })
@@ -141,9 +165,9 @@ fn node_contents(node: &NodeData, actions: impl CallTreeActions) -> tree::Custom
         NodeStatus::NotRun => icon::base::circle_task(),
         NodeStatus::Running => icon::base::busy(),
         NodeStatus::Complete => icon::base::sys_enter(),
+        NodeStatus::Failed => icon::base::error(),
         // TODO:
         // NodeStatus::PredicateSuccessful(false) => icon::base::circle_task_2(),
-        // NodeStatus::Failed => icon::base::error(),
     });
     let badge = run_status.signal().map(|run_status| {
         if run_status == NodeStatus::Running {
@@ -162,6 +186,7 @@ fn node_contents(node: &NodeData, actions: impl CallTreeActions) -> tree::Custom
         .design(design)
         .text(node.typ.as_display_name())
         .icon(Sig(icon))
+        .tooltip(Sig(node.error.signal_cloned()))
         .end_icon(icon::base::slim_arrow_down())
         .menu_opener(&menu)
         .badge_optional_child(Sig(badge));
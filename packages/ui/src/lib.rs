@@ -1,7 +1,11 @@
 use codastraea_frontend::ServerConnection;
+use futures_signals::signal::{Mutable, SignalExt};
 use silkenweb::{
+    clone,
     elements::html::div,
     node::element::{ChildElement, Element, ParentElement},
+    task::spawn_local,
+    value::Sig,
 };
 use thread_view::ThreadView;
 
@@ -26,8 +30,17 @@ mod css {
 
 pub fn app() -> impl ChildElement {
     let server_connection = ServerConnection::default();
+    // TODO: Choose which run to view.
+    let run_id = Mutable::new(None);
+
+    spawn_local({
+        clone!(server_connection, run_id);
+        async move { run_id.set(server_connection.runs().await.first().copied()) }
+    });
 
     div()
         .class(css::full_height())
-        .child(ThreadView::new(server_connection))
+        .optional_child(Sig(run_id.signal().map(move |run_id| {
+            run_id.map(|run_id| ThreadView::new(server_connection.clone(), run_id))
+        })))
 }
@@ -1,5 +1,5 @@
 use codastraea_frontend::ServerConnection;
-use codastraea_server_api::SrcSpan;
+use codastraea_server_api::{RunId, SrcSpan};
 use derive_more::Into;
 use futures_signals::signal::{Mutable, SignalExt};
 use silkenweb::{
@@ -23,7 +23,7 @@ use crate::{
 pub struct ThreadView(Node);
 
 impl ThreadView {
-    pub fn new(server: ServerConnection) -> Self {
+    pub fn new(server: ServerConnection, run_id: RunId) -> Self {
         let editor = Editor::new("Some code");
         let tab_group = tab::container().class(css::full_height());
         let selected_tab = Mutable::new(Tab::CallTree);
@@ -31,7 +31,7 @@ impl ThreadView {
             selected_tab: selected_tab.clone(),
             editor: editor.clone(),
         };
-        let call_tree_view = CallTreeView::new(server, actions);
+        let call_tree_view = CallTreeView::new(server, run_id, actions);
         let tab = |tab: Tab| {
             tab::content().text(tab.as_ref()).selected(Sig(selected_tab
                 .signal()
@@ -6,7 +6,7 @@ use syn::{
     parse::Parse,
     parse_macro_input, parse_quote,
     spanned::Spanned,
-    Block, Error, Expr, ExprBlock, ExprIf, Ident, ItemFn, Result,
+    Block, Error, Expr, ExprBlock, ExprIf, Ident, ItemFn, Result, ReturnType,
 };
 
 #[proc_macro_attribute]
@@ -42,6 +42,13 @@ fn impl_workflow(
     let ident = &sig.ident;
     let name = &ident.to_string();
     let block = fold_block(&mut Instrument, *block);
+    // Workflows that return a value report it to the host when they complete.
+    let main = match &sig.output {
+        ReturnType::Default => quote! { #ident() },
+        ReturnType::Type(..) => quote! {
+            async { ::codastraea_wasm_guest::set_return_value(&#ident().await) }
+        },
+    };
 
     Ok(quote! {
         #(#attrs)*
@@ -58,7 +65,7 @@ fn impl_workflow(
         ::codastraea_wasm_guest::inventory::submit!(
             {
                 fn set_main_fn() {
-                    ::codastraea_wasm_guest::set_main_fn(#ident());
+                    ::codastraea_wasm_guest::set_main_fn(#main);
                 }
 
                 ::codastraea_wasm_guest::Workflow::new(
@@ -192,20 +199,15 @@ impl Instrument {
     fn traced<T: Spanned + ToTokens + Parse>(node_type: &NodeType, item: T) -> Block {
         let trace_type = node_type.as_snake_str();
         let span = item.span();
-        let begin = Ident::new(&format!("__codastraea_begin_{trace_type}"), span);
-        let end = Ident::new(&format!("__codastraea_end_{trace_type}"), span);
+        let begin = Ident::new(&format!("begin_{trace_type}"), span);
+        let end = Ident::new(&format!("end_{trace_type}"), span);
         let trace_guard = Self::trace_guard(span);
 
         parse_quote! {
             {
-                extern "C" {
-                    fn #begin();
-                    fn #end();
-                }
-
-                unsafe{ #begin() }
+                ::codastraea_wasm_guest::trace::#begin();
                 let #trace_guard = ::codastraea_wasm_guest::OnDrop::new(
-                    || unsafe { #end() }
+                    ::codastraea_wasm_guest::trace::#end
                 );
 
                 (#item)
@@ -1,5 +1,5 @@
 use std::{
-    cell::Cell,
+    cell::{Cell, RefCell},
     future::Future,
     pin::Pin,
     sync::Arc,
@@ -9,24 +9,47 @@ use std::{
 use crossbeam::sync::{Parker, Unparker};
 
 #[must_use = "checkpoints do nothing unless you `.await` or poll them"]
-pub struct Checkpoint;
+pub struct Checkpoint {
+    label: Option<String>,
+    reached: bool,
+}
 
 impl Future for Checkpoint {
     type Output = ();
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if AT_CHECKPOINT.get() {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Only stop when we're polled, so checkpoints that are built, but never
+        // awaited, don't affect the next one.
+        if self.reached {
+            Poll::Ready(())
+        } else {
+            self.reached = true;
+            AT_CHECKPOINT.set(true);
+            LABEL.set(self.label.take());
             cx.waker().wake_by_ref();
             Poll::Pending
-        } else {
-            Poll::Ready(())
         }
     }
 }
 
 pub fn checkpoint() -> Checkpoint {
-    AT_CHECKPOINT.set(true);
-    Checkpoint
+    Checkpoint {
+        label: None,
+        reached: false,
+    }
+}
+
+/// A checkpoint, with a label the host can show.
+pub fn labelled_checkpoint(label: impl Into<String>) -> Checkpoint {
+    Checkpoint {
+        label: Some(label.into()),
+        reached: false,
+    }
+}
+
+/// Take the label of the checkpoint we stopped at, if it has one.
+pub fn take_label() -> Option<String> {
+    LABEL.take()
 }
 
 struct ThreadWaker(Unparker);
@@ -64,4 +87,5 @@ pub fn until_checkpoint<T>(mut fut: Pin<&mut (impl Future<Output = T> + ?Sized)>
 
 thread_local! {
     static AT_CHECKPOINT: Cell<bool> = const { Cell::new(false) };
+    static LABEL: RefCell<Option<String>> = const { RefCell::new(None) };
 }
@@ -0,0 +1,35 @@
+//! The guest side of the component model interface, in
+//! `wasm-host/wit/codastraea.wit`.
+//!
+//! Build the guest for `wasm32-wasip2`, or convert a core module with
+//! `wasm-tools component new`.
+use crate::{init_workflow, register_workflows, run, ABI_VERSION};
+
+pub(crate) mod bindings {
+    wit_bindgen::generate!({
+        path: "../wasm-host/wit",
+        world: "guest",
+    });
+}
+
+struct Component;
+
+impl bindings::Guest for Component {
+    fn abi_version() -> u32 {
+        ABI_VERSION
+    }
+
+    fn register_workflows() -> u32 {
+        register_workflows()
+    }
+
+    fn init_workflow(index: u32) {
+        init_workflow(index)
+    }
+
+    fn run() -> bool {
+        run()
+    }
+}
+
+bindings::export!(Component with_types_in bindings);
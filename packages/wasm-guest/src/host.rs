@@ -1,3 +1,83 @@
+//! Calls into the host.
+//!
+//! Core modules import these from the `codastraea` module. With the
+//! `component` feature, they're forwarded to the WIT imports instead.
+
+#[cfg(not(feature = "component"))]
+pub fn log(message: &str) {
+    unsafe { __codastraea_log(wasm_ptr(message), wasm_len(message)) };
+}
+
+#[cfg(not(feature = "component"))]
+pub fn register_workflow_index(module: &str, name: &str, index: u32) {
+    unsafe {
+        __codastraea_register_workflow_index(
+            wasm_ptr(module),
+            wasm_len(module),
+            wasm_ptr(name),
+            wasm_len(name),
+            index,
+        )
+    }
+}
+
+#[cfg(not(feature = "component"))]
+pub fn fn_begin(module: &str, name: &str) {
+    unsafe {
+        __codastraea_fn_begin(
+            wasm_ptr(module),
+            wasm_len(module),
+            wasm_ptr(name),
+            wasm_len(name),
+        )
+    }
+}
+
+#[cfg(not(feature = "component"))]
+pub fn fn_end(module: &str, name: &str) {
+    unsafe {
+        __codastraea_fn_end(
+            wasm_ptr(module),
+            wasm_len(module),
+            wasm_ptr(name),
+            wasm_len(name),
+        )
+    }
+}
+
+#[cfg(not(feature = "component"))]
+pub fn checkpoint_label(label: &str) {
+    unsafe { __codastraea_checkpoint_label(wasm_ptr(label), wasm_len(label)) };
+}
+
+#[cfg(not(feature = "component"))]
+pub fn return_value(value: &str) {
+    unsafe { __codastraea_return_value(wasm_ptr(value), wasm_len(value)) };
+}
+
+#[cfg(not(feature = "component"))]
+pub fn panic(message: &str) {
+    unsafe { __codastraea_panic(wasm_ptr(message), wasm_len(message)) };
+}
+
+#[cfg(not(feature = "component"))]
+fn wasm_ptr(s: &str) -> u32 {
+    (s.as_ptr() as usize).try_into().unwrap()
+}
+
+#[cfg(not(feature = "component"))]
+fn wasm_len(s: &str) -> u32 {
+    s.len().try_into().unwrap()
+}
+
+#[cfg(feature = "component")]
+pub use crate::component::bindings::codastraea::workflow::host::{
+    begin, checkpoint_label, end, fn_begin, fn_end, log, panic, register_workflow_index,
+    return_value, NodeKind,
+};
+
+#[cfg(not(feature = "component"))]
+#[link(wasm_import_module = "codastraea")]
 unsafe extern "C" {
     pub fn __codastraea_log(data: u32, len: u32);
     pub fn __codastraea_register_workflow_index(
@@ -11,6 +91,10 @@ unsafe extern "C" {
     pub fn __codastraea_fn_begin(module: u32, module_len: u32, name: u32, name_len: u32);
     pub fn __codastraea_fn_end(module: u32, module_len: u32, name: u32, name_len: u32);
 
+    pub fn __codastraea_checkpoint_label(data: u32, len: u32);
+    pub fn __codastraea_return_value(data: u32, len: u32);
+    pub fn __codastraea_panic(data: u32, len: u32);
+
     pub fn __codastraea_begin_if();
     pub fn __codastraea_end_if();
     pub fn __codastraea_begin_condition();
@@ -23,7 +107,7 @@ unsafe extern "C" {
     pub fn __codastraea_end_else();
 }
 
-#[cfg(not(target_family = "wasm"))]
+#[cfg(all(not(target_family = "wasm"), not(feature = "component")))]
 mod define {
     #[no_mangle]
     unsafe extern "C" fn __codastraea_log(_data: u32, _len: u32) {}
@@ -56,6 +140,15 @@ mod define {
     ) {
     }
 
+    #[no_mangle]
+    unsafe extern "C" fn __codastraea_checkpoint_label(_data: u32, _len: u32) {}
+
+    #[no_mangle]
+    unsafe extern "C" fn __codastraea_return_value(_data: u32, _len: u32) {}
+
+    #[no_mangle]
+    unsafe extern "C" fn __codastraea_panic(_data: u32, _len: u32) {}
+
     #[no_mangle]
     unsafe extern "C" fn __codastraea_begin_if() {}
 
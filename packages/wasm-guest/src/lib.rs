@@ -1,10 +1,12 @@
-use std::{cell::RefCell, future::Future, pin::Pin};
+use std::{cell::RefCell, fmt::Debug, future::Future, panic, pin::Pin};
 
 use checkpoint::until_checkpoint;
 
 mod checkpoint;
+#[cfg(feature = "component")]
+mod component;
 
-pub use checkpoint::checkpoint;
+pub use checkpoint::{checkpoint, labelled_checkpoint};
 /// Make a Workflow function.
 ///
 /// This instruments a function to trace any control flow, so it can be used as
@@ -17,6 +19,8 @@ pub use codastraea_wasm_guest_proc_macro::workflow;
 pub use inventory;
 
 mod host;
+#[doc(hidden)]
+pub mod trace;
 
 #[doc(hidden)]
 pub struct TraceFn {
@@ -26,29 +30,14 @@ pub struct TraceFn {
 
 impl TraceFn {
     pub fn new(module: &'static str, name: &'static str) -> Self {
-        unsafe {
-            host::__codastraea_fn_begin(
-                wasm_ptr(module),
-                wasm_len(module),
-                wasm_ptr(name),
-                wasm_len(name),
-            )
-        }
-
+        host::fn_begin(module, name);
         Self { module, name }
     }
 }
 
 impl Drop for TraceFn {
     fn drop(&mut self) {
-        unsafe {
-            host::__codastraea_fn_end(
-                wasm_ptr(self.module),
-                wasm_len(self.module),
-                wasm_ptr(self.name),
-                wasm_len(self.name),
-            )
-        }
+        host::fn_end(self.module, self.name)
     }
 }
 
@@ -70,16 +59,7 @@ impl<F: Fn()> OnDrop<F> {
 }
 
 pub fn log(s: impl AsRef<str>) {
-    let s = s.as_ref();
-    unsafe { host::__codastraea_log(wasm_ptr(s), wasm_len(s)) };
-}
-
-fn wasm_ptr(s: &str) -> u32 {
-    (s.as_ptr() as usize).try_into().unwrap()
-}
-
-fn wasm_len(s: &str) -> u32 {
-    s.len().try_into().unwrap()
+    host::log(s.as_ref());
 }
 
 #[doc(hidden)]
@@ -97,23 +77,54 @@ impl Workflow {
     }
 }
 
+/// The version of the interface between this library and the host.
+///
+/// Bump this whenever an import or export changes. The host checks it's in
+/// the range of versions it supports.
+const ABI_VERSION: u32 = 4;
+
+#[cfg(not(feature = "component"))]
+#[no_mangle]
+extern "C" fn __codastraea_abi_version() -> u32 {
+    ABI_VERSION
+}
+
+/// The ABI version, in a custom section, so the host can check it before
+/// instantiating the guest.
+#[cfg(not(feature = "component"))]
+#[used]
+#[link_section = "codastraea_abi_version"]
+static ABI_VERSION_SECTION: [u8; 4] = ABI_VERSION.to_le_bytes();
+
+#[cfg(not(feature = "component"))]
 #[no_mangle]
 extern "C" fn __codastraea_register_workflows() -> u32 {
+    register_workflows()
+}
+
+#[cfg(not(feature = "component"))]
+#[no_mangle]
+extern "C" fn __codastraea_init_workflow(index: u32) {
+    init_workflow(index)
+}
+
+#[cfg(not(feature = "component"))]
+#[no_mangle]
+extern "C" fn __codastraea_run() -> i32 {
+    run().into()
+}
+
+fn register_workflows() -> u32 {
+    // Panics abort the guest with a trap, which doesn't include the panic
+    // message, so report it first.
+    panic::set_hook(Box::new(|info| host::panic(&info.to_string())));
     log("Registering workflows");
 
     WORKFLOWS.with_borrow_mut(|workflows| {
         for (index, Workflow { module, name, init }) in
             inventory::iter::<Workflow>.into_iter().enumerate()
         {
-            unsafe {
-                host::__codastraea_register_workflow_index(
-                    wasm_ptr(module),
-                    wasm_len(module),
-                    wasm_ptr(name),
-                    wasm_len(name),
-                    index.try_into().unwrap(),
-                )
-            }
+            host::register_workflow_index(module, name, index.try_into().unwrap());
             workflows.push(*init);
         }
 
@@ -121,18 +132,25 @@ extern "C" fn __codastraea_register_workflows() -> u32 {
     })
 }
 
-#[no_mangle]
-extern "C" fn __codastraea_init_workflow(index: u32) {
+fn init_workflow(index: u32) {
     let index = usize::try_from(index).unwrap();
     WORKFLOWS.with_borrow(|workflows| workflows[index]())
 }
 
-#[no_mangle]
-extern "C" fn __codastraea_run() -> i32 {
-    MAIN.with_borrow_mut(|f| match until_checkpoint(f.as_mut()) {
-        Some(_) => 0,
-        None => 1,
-    })
+/// Run until the next checkpoint. Returns `true` if we stopped at a
+/// checkpoint, or `false` if the workflow is complete.
+fn run() -> bool {
+    let at_checkpoint = MAIN.with_borrow_mut(|f| until_checkpoint(f.as_mut()).is_none());
+
+    if at_checkpoint {
+        if let Some(label) = checkpoint::take_label() {
+            host::checkpoint_label(&label);
+        }
+    } else if let Some(value) = RETURN_VALUE.take() {
+        host::return_value(&value);
+    }
+
+    at_checkpoint
 }
 
 #[doc(hidden)]
@@ -140,9 +158,17 @@ pub fn set_main_fn(f: impl Future<Output = ()> + 'static) {
     MAIN.set(Box::pin(f));
 }
 
+/// Keep the workflow's return value, formatted with [`Debug`], to report to
+/// the host when `run` completes.
+#[doc(hidden)]
+pub fn set_return_value(value: &impl Debug) {
+    RETURN_VALUE.set(Some(format!("{value:?}")));
+}
+
 async fn noop() {}
 
 thread_local! {
     static MAIN: RefCell<Pin<Box<dyn Future<Output = ()>>>> = RefCell::new(Box::pin(noop()));
     static WORKFLOWS: RefCell<Vec<fn()>> = const { RefCell::new(Vec::new()) };
+    static RETURN_VALUE: RefCell<Option<String>> = const { RefCell::new(None) };
 }
@@ -0,0 +1,40 @@
+//! Calls generated by `#[workflow]`, to trace control flow.
+//!
+//! Core modules call the `__codastraea_begin_*` and `__codastraea_end_*`
+//! imports. With the `component` feature, they're forwarded to the WIT `begin`
+//! and `end` imports instead.
+
+macro_rules! trace_nodes {
+    ($($kind:ident: $begin:ident, $end:ident, $begin_import:ident, $end_import:ident;)*) => {
+        $(
+            pub fn $begin() {
+                #[cfg(not(feature = "component"))]
+                unsafe {
+                    crate::host::$begin_import()
+                }
+
+                #[cfg(feature = "component")]
+                crate::host::begin(crate::host::NodeKind::$kind)
+            }
+
+            pub fn $end() {
+                #[cfg(not(feature = "component"))]
+                unsafe {
+                    crate::host::$end_import()
+                }
+
+                #[cfg(feature = "component")]
+                crate::host::end(crate::host::NodeKind::$kind)
+            }
+        )*
+    };
+}
+
+trace_nodes! {
+    If: begin_if, end_if, __codastraea_begin_if, __codastraea_end_if;
+    Condition: begin_condition, end_condition, __codastraea_begin_condition,
+        __codastraea_end_condition;
+    Then: begin_then, end_then, __codastraea_begin_then, __codastraea_end_then;
+    ElseIf: begin_else_if, end_else_if, __codastraea_begin_else_if, __codastraea_end_else_if;
+    Else: begin_else, end_else, __codastraea_begin_else, __codastraea_end_else;
+}
@@ -1,42 +1,92 @@
-use std::path::PathBuf;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+};
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Result};
 use clap::Parser;
-use codastraea_wasm_host::runtime::Container;
+use codastraea_wasm_host::{
+    cache::ModuleCache,
+    outcome::RunOutcome,
+    runtime::{Container, ContainerBuilder, ContainerSnapshot},
+    snapshot::SnapshotOptions,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// The WASM module. This can be text or binary format
     file: PathBuf,
+    /// Persist the snapshot to this file, and restore from it
+    #[arg(long)]
+    snapshot_file: Option<PathBuf>,
+    /// Check the restored state matches the snapshot
+    #[arg(long)]
+    verify: bool,
+    /// Cache instrumented and compiled modules in this directory
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
-    let wat_file = Args::parse().file;
-    let mut container = Container::from_file(&wat_file)?;
+    let Args {
+        file: wat_file,
+        snapshot_file,
+        verify,
+        cache_dir,
+    } = Args::parse();
+    let mut builder = ContainerBuilder::new();
+
+    if let Some(cache_dir) = cache_dir {
+        builder = builder.cache(ModuleCache::new(cache_dir)?);
+    }
+
+    let mut container = builder.build_from_file(&wat_file)?;
+    container.set_snapshot_options(SnapshotOptions {
+        verify,
+        ..SnapshotOptions::default()
+    });
 
     container.register_workflows()?;
     container.init_workflow("codastraea_test_workflow", "counter")?;
 
     for _i in 0..5 {
-        container.run()?;
-        println!("Checkpoint (pre snapshot)");
+        ensure!(
+            run(&mut container, "pre snapshot")?,
+            "Workflow finished before the snapshot"
+        );
     }
 
     let snapshot = container.snapshot()?;
 
-    while container.run()? {
-        println!("Checkpoint (post snapshot)");
-    }
+    while run(&mut container, "post snapshot")? {}
 
     drop(container);
 
-    let mut container = Container::from_file(&wat_file)?;
+    let snapshot = if let Some(snapshot_file) = snapshot_file {
+        snapshot.write_to(BufWriter::new(File::create(&snapshot_file)?))?;
+        ContainerSnapshot::read_from(BufReader::new(File::open(&snapshot_file)?))?
+    } else {
+        snapshot
+    };
+
+    let mut container = builder.build_from_file(&wat_file)?;
     container.restore(&snapshot)?;
 
-    while container.run()? {
-        println!("Checkpoint (post restore)");
-    }
+    while run(&mut container, "post restore")? {}
 
     Ok(())
 }
+
+/// Run until the next checkpoint, and return whether we stopped at one.
+fn run(container: &mut Container, stage: &str) -> Result<bool> {
+    let outcome = container.run()?;
+    println!("{outcome} ({stage})");
+
+    match outcome {
+        RunOutcome::Checkpoint { .. } => Ok(true),
+        RunOutcome::Completed { .. } => Ok(false),
+        outcome => bail!("Workflow failed: {outcome}"),
+    }
+}
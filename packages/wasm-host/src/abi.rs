@@ -0,0 +1,275 @@
+//! The interface between the host and guests built with
+//! `codastraea-wasm-guest`.
+//!
+//! Every ABI function only uses `i32`s. Components use the equivalent WIT
+//! interface in `wit/codastraea.wit` instead. See `ABI.md` for the full
+//! specification.
+use std::ops::RangeInclusive;
+
+use anyhow::{bail, ensure, Result};
+use codastraea_server_api::NodeType;
+use wasmparser::{Parser, Payload};
+use wasmtime::{AsContextMut, ExternType, FuncType, Instance, Module};
+
+/// The `codastraea-wasm-guest` version this host expects.
+///
+/// The guest and host libraries are versioned together.
+pub const GUEST_LIBRARY_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The ABI versions this host supports.
+///
+/// Guests export their version from `__codastraea_abi_version`, and can also
+/// declare it in a custom section. See [`declared_version`].
+pub const SUPPORTED_ABI_VERSIONS: RangeInclusive<u32> = 1..=4;
+
+/// The ABI version of guests built before `__codastraea_abi_version` was
+/// added.
+const UNVERSIONED_ABI: u32 = 1;
+
+const ABI_VERSION_EXPORT: &str = "__codastraea_abi_version";
+
+/// The custom section guests can declare their ABI version in, as a
+/// little-endian `u32`.
+const ABI_VERSION_SECTION: &str = "codastraea_abi_version";
+
+/// The module the host's imports are defined in.
+pub(crate) const LINKER_MODULE: &str = "codastraea";
+
+/// The module guests imported from before ABI version 2.
+///
+/// Imports are also defined here, so older guests, and guests that use both
+/// modules, still link. Guests can use `env` for other imports, as long as
+/// they don't start with `__codastraea_`.
+pub(crate) const LEGACY_LINKER_MODULE: &str = "env";
+
+/// Node types with `__codastraea_begin_*` and `__codastraea_end_*` imports.
+pub(crate) const TRACED_NODE_TYPES: [NodeType; 5] = [
+    NodeType::If,
+    NodeType::Condition,
+    NodeType::Then,
+    NodeType::ElseIf,
+    NodeType::Else,
+];
+
+/// The number of `i32` params and results of an ABI function.
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct Signature {
+    params: usize,
+    results: usize,
+}
+
+impl Signature {
+    const fn new(params: usize, results: usize) -> Self {
+        Self { params, results }
+    }
+
+    fn matches(self, ty: &FuncType) -> bool {
+        ty.params().len() == self.params
+            && ty.results().len() == self.results
+            && ty.params().chain(ty.results()).all(|ty| ty.is_i32())
+    }
+}
+
+/// Functions the host provides.
+///
+/// Guests only need to import the ones they use.
+fn imports() -> Vec<(String, Signature)> {
+    let mut imports = vec![
+        ("__codastraea_log".to_string(), Signature::new(2, 0)),
+        (
+            "__codastraea_register_workflow_index".to_string(),
+            Signature::new(5, 0),
+        ),
+        ("__codastraea_fn_begin".to_string(), Signature::new(4, 0)),
+        ("__codastraea_fn_end".to_string(), Signature::new(4, 0)),
+        (
+            "__codastraea_checkpoint_label".to_string(),
+            Signature::new(2, 0),
+        ),
+        (
+            "__codastraea_return_value".to_string(),
+            Signature::new(2, 0),
+        ),
+        ("__codastraea_panic".to_string(), Signature::new(2, 0)),
+    ];
+
+    for node_type in &TRACED_NODE_TYPES {
+        let snake_name = node_type.as_snake_str();
+
+        for event in ["begin", "end"] {
+            imports.push((
+                format!("__codastraea_{event}_{snake_name}"),
+                Signature::new(0, 0),
+            ));
+        }
+    }
+
+    imports
+}
+
+/// Functions the guest must export.
+const EXPORTS: [(&str, Signature); 3] = [
+    ("__codastraea_register_workflows", Signature::new(0, 1)),
+    ("__codastraea_init_workflow", Signature::new(1, 0)),
+    ("__codastraea_run", Signature::new(0, 1)),
+];
+
+/// Check `module` implements the guest side of the ABI.
+///
+/// The error lists every missing or mismatched import and export.
+pub fn validate(module: &Module) -> Result<()> {
+    let host_imports = imports();
+    let mut problems = Vec::new();
+
+    for import in module.imports() {
+        let name = import.name();
+        let is_abi_name = name.starts_with("__codastraea_");
+
+        match import.module() {
+            LINKER_MODULE => (),
+            LEGACY_LINKER_MODULE if is_abi_name => (),
+            module => {
+                if is_abi_name {
+                    problems.push(format!(
+                        "Import '{name}' should be in module '{LINKER_MODULE}', not '{module}'"
+                    ));
+                }
+
+                continue;
+            }
+        }
+
+        let Some((_, signature)) = host_imports.iter().find(|(host_name, _)| host_name == name)
+        else {
+            problems.push(format!("Import '{name}' isn't provided by the host"));
+            continue;
+        };
+
+        match import.ty() {
+            ExternType::Func(ty) if signature.matches(&ty) => (),
+            ExternType::Func(ty) => {
+                problems.push(format!("Import '{name}' has the wrong signature: {ty}"))
+            }
+            _ => problems.push(format!("Import '{name}' should be a function")),
+        }
+    }
+
+    for (name, signature) in EXPORTS {
+        match module.get_export(name) {
+            Some(ExternType::Func(ty)) if signature.matches(&ty) => (),
+            Some(ExternType::Func(ty)) => {
+                problems.push(format!("Export '{name}' has the wrong signature: {ty}"))
+            }
+            Some(_) => problems.push(format!("Export '{name}' should be a function")),
+            None => problems.push(format!("Export '{name}' is missing")),
+        }
+    }
+
+    match module.get_export(ABI_VERSION_EXPORT) {
+        Some(ExternType::Func(ty)) if Signature::new(0, 1).matches(&ty) => (),
+        Some(ExternType::Func(ty)) => problems.push(format!(
+            "Export '{ABI_VERSION_EXPORT}' has the wrong signature: {ty}"
+        )),
+        Some(_) => problems.push(format!(
+            "Export '{ABI_VERSION_EXPORT}' should be a function"
+        )),
+        None => (),
+    }
+
+    match module.get_export("memory") {
+        Some(ExternType::Memory(_)) => (),
+        Some(_) => problems.push("Export 'memory' should be a memory".to_string()),
+        None => problems.push("Export 'memory' is missing".to_string()),
+    }
+
+    if !problems.is_empty() {
+        bail!(
+            "Module doesn't match the codastraea guest ABI. Was it built with \
+             `codastraea-wasm-guest` version {GUEST_LIBRARY_VERSION}?\n{}",
+            problems.join("\n")
+        );
+    }
+
+    Ok(())
+}
+
+/// The ABI version `original` declares in its custom section, if it has one.
+///
+/// This doesn't need an instance, so the host can check the version before
+/// [`validate`]. Otherwise, a guest built for a newer ABI would get errors
+/// about imports the host doesn't provide, instead of a version error.
+///
+/// `original` must be in the binary format.
+pub fn declared_version(original: &[u8]) -> Result<Option<u32>> {
+    for payload in Parser::new(0).parse_all(original) {
+        let Payload::CustomSection(section) = payload? else {
+            continue;
+        };
+
+        if section.name() == ABI_VERSION_SECTION {
+            let Ok(version) = section.data().try_into() else {
+                bail!("Custom section '{ABI_VERSION_SECTION}' should be a little-endian `u32`");
+            };
+
+            return Ok(Some(u32::from_le_bytes(version)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Get the guest's ABI version, and check the host supports it.
+pub fn check_version(ctx: &mut impl AsContextMut, instance: &Instance) -> Result<u32> {
+    let version = match instance.get_func(&mut *ctx, ABI_VERSION_EXPORT) {
+        Some(func) => func.typed::<(), u32>(&*ctx)?.call(&mut *ctx, ())?,
+        None => UNVERSIONED_ABI,
+    };
+
+    check_supported(version)?;
+    Ok(version)
+}
+
+/// Check the version the guest exports matches the version it declares, if
+/// it declares one. See [`declared_version`].
+pub(crate) fn check_declared(declared: Option<u32>, exported: u32) -> Result<()> {
+    if let Some(declared) = declared {
+        ensure!(
+            declared == exported,
+            "Guest declares ABI version {declared} in custom section '{ABI_VERSION_SECTION}', \
+             but '{ABI_VERSION_EXPORT}' returns {exported}"
+        );
+    }
+
+    Ok(())
+}
+
+/// [`check_version`], for stores with async support.
+pub async fn check_version_async(
+    ctx: &mut impl AsContextMut<Data: Send>,
+    instance: &Instance,
+) -> Result<u32> {
+    let version = match instance.get_func(&mut *ctx, ABI_VERSION_EXPORT) {
+        Some(func) => {
+            func.typed::<(), u32>(&*ctx)?
+                .call_async(&mut *ctx, ())
+                .await?
+        }
+        None => UNVERSIONED_ABI,
+    };
+
+    check_supported(version)?;
+    Ok(version)
+}
+
+/// Check the host supports ABI `version`.
+pub(crate) fn check_supported(version: u32) -> Result<()> {
+    ensure!(
+        SUPPORTED_ABI_VERSIONS.contains(&version),
+        "Guest ABI version {version} isn't supported. This host supports versions {} to {}. \
+         Try rebuilding the guest with `codastraea-wasm-guest` version {GUEST_LIBRARY_VERSION}.",
+        SUPPORTED_ABI_VERSIONS.start(),
+        SUPPORTED_ABI_VERSIONS.end()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use codastraea_wasm_host::{
+    runtime::{ContainerSnapshot, ContainerSnapshotChain},
+    snapshot::{chain::SnapshotChain, read_kind, Snapshot, SnapshotKind},
+};
+
+/// View and diff persisted snapshots
+///
+/// Files can contain any kind of snapshot or snapshot chain.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+enum Commands {
+    /// List the globals, memories and tables in a snapshot, or in each
+    /// snapshot in a chain
+    Show { file: PathBuf },
+    /// Show the differences between two snapshots
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// The index of the snapshot to use if `old` is a chain. Defaults to
+        /// the last one.
+        #[arg(long)]
+        old_index: Option<usize>,
+        /// The index of the snapshot to use if `new` is a chain. Defaults to
+        /// the last one.
+        #[arg(long)]
+        new_index: Option<usize>,
+    },
+}
+
+fn main() -> Result<()> {
+    match Commands::parse() {
+        Commands::Show { file } => {
+            let snapshots = load(&file)?;
+            let is_chain = snapshots.len() > 1;
+
+            for (index, snapshot) in snapshots.iter().enumerate() {
+                if is_chain {
+                    println!("Snapshot {index}:");
+                }
+
+                print!("{}", snapshot.summary());
+            }
+        }
+        Commands::Diff {
+            old,
+            new,
+            old_index,
+            new_index,
+        } => {
+            let old = select(load(&old)?, old_index)?;
+            let new = select(load(&new)?, new_index)?;
+            let diff = old.diff(&new)?;
+
+            if diff.is_empty() {
+                println!("Snapshots are identical");
+            } else {
+                print!("{diff}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Load each snapshot in `file`, using the kind from its header.
+fn load(file: &Path) -> Result<Vec<Snapshot>> {
+    let bytes = fs::read(file).with_context(|| format!("Opening {}", file.display()))?;
+    let reader = bytes.as_slice();
+    let kind = read_kind(reader).with_context(|| format!("Reading {}", file.display()))?;
+    println!("{}: {kind:?}", file.display());
+
+    match kind {
+        SnapshotKind::Wasm => Ok(vec![Snapshot::read_from(reader)?]),
+        SnapshotKind::Container => {
+            let snapshot = ContainerSnapshot::read_from(reader)?;
+            println!("{}: {}", file.display(), snapshot.fingerprint());
+            Ok(vec![snapshot.wasm().clone()])
+        }
+        SnapshotKind::Chain => {
+            let chain = SnapshotChain::read_from(reader)?;
+            (0..chain.len()).map(|index| chain.get(index)).collect()
+        }
+        SnapshotKind::ContainerChain => {
+            let chain = ContainerSnapshotChain::read_from(reader)?;
+            println!("{}: {}", file.display(), chain.fingerprint());
+            (0..chain.len())
+                .map(|index| Ok(chain.get(index)?.wasm().clone()))
+                .collect()
+        }
+    }
+}
+
+/// Select the snapshot at `index`, or the last snapshot.
+fn select(mut snapshots: Vec<Snapshot>, index: Option<usize>) -> Result<Snapshot> {
+    let len = snapshots.len();
+    let index = index.unwrap_or(len - 1);
+
+    ensure!(
+        index < len,
+        "Snapshot {index} is out of range for a chain of length {len}"
+    );
+
+    Ok(snapshots.swap_remove(index))
+}
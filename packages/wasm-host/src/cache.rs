@@ -0,0 +1,188 @@
+//! An on-disk cache of instrumented and compiled guest modules.
+use std::{
+    fs::{self, File},
+    hash::{Hash as _, Hasher},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use wasmtime::{Engine, Module};
+
+use crate::{
+    abi,
+    fingerprint::{Hash, ModuleFingerprint},
+    instrument::{instrument, Instrumented},
+    snapshot::{
+        migrate::StaticData,
+        persist::{read_bytes, write_bytes, Persist},
+    },
+};
+
+/// Bump this whenever instrumentation or the cache entry format changes, so
+/// stale entries aren't used.
+const CACHE_VERSION: u32 = 1;
+
+/// A guest module, instrumented and compiled.
+///
+/// This is cheap to clone, so one module can be used for many containers. See
+/// [`ContainerBuilder::build_from_module`][crate::runtime::ContainerBuilder::build_from_module].
+#[derive(Clone)]
+pub struct CompiledModule {
+    pub(crate) module: Module,
+    pub(crate) fingerprint: ModuleFingerprint,
+    pub(crate) static_data: StaticData,
+    /// The ABI version the module declares, from [`abi::declared_version`].
+    pub(crate) abi_version: Option<u32>,
+}
+
+impl CompiledModule {
+    /// Instrument and compile `original`, which must be in the binary format.
+    pub fn new(engine: &Engine, original: &[u8]) -> Result<Self> {
+        let instrumented = instrument(original)?;
+        let module = Module::new(engine, &instrumented.module)?;
+        Self::from_parts(original, instrumented, module)
+    }
+
+    pub fn engine(&self) -> &Engine {
+        self.module.engine()
+    }
+
+    pub fn fingerprint(&self) -> ModuleFingerprint {
+        self.fingerprint
+    }
+
+    fn from_parts(original: &[u8], instrumented: Instrumented, module: Module) -> Result<Self> {
+        let unstable_names = instrumented.unstable_names;
+
+        if !unstable_names.is_empty() {
+            println!(
+                "Warning: Snapshots won't survive rebuilds of the guest, as these items have \
+                 unstable names: {}",
+                unstable_names.join(", ")
+            );
+        }
+
+        Ok(Self {
+            module,
+            fingerprint: ModuleFingerprint::new(original, &instrumented.module),
+            static_data: instrumented.static_data,
+            abi_version: abi::declared_version(original)?,
+        })
+    }
+}
+
+/// A directory of instrumented modules and their compiled artifacts.
+///
+/// Entries are keyed by a hash of the original module and the engine's
+/// configuration, so changing either just causes a cache miss.
+///
+/// Compiled artifacts are loaded without validation, so anyone who can write
+/// to the cache directory can run arbitrary code in the host.
+#[derive(Clone, Debug)]
+pub struct ModuleCache {
+    dir: PathBuf,
+}
+
+impl ModuleCache {
+    /// Use `dir` as a cache. It's created if it doesn't exist.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).with_context(|| format!("Creating cache dir {dir:?}"))?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Get the compiled module for `original` from the cache, or instrument
+    /// and compile it, and add it to the cache.
+    ///
+    /// `original` must be in the binary format.
+    pub fn get_or_compile(&self, engine: &Engine, original: &[u8]) -> Result<CompiledModule> {
+        let path = self
+            .dir
+            .join(format!("{}.module", cache_key(engine, original)));
+
+        if path.exists() {
+            match self.load(engine, original, &path) {
+                Ok(module) => return Ok(module),
+                Err(e) => println!("Warning: Ignoring cache entry {path:?}: {e:#}"),
+            }
+        }
+
+        let instrumented = instrument(original)?;
+        let module = Module::new(engine, &instrumented.module)?;
+        self.store(&path, &instrumented, &module)
+            .with_context(|| format!("Writing cache entry {path:?}"))?;
+
+        CompiledModule::from_parts(original, instrumented, module)
+    }
+
+    fn load(&self, engine: &Engine, original: &[u8], path: &Path) -> Result<CompiledModule> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let instrumented = Instrumented {
+            module: read_bytes(&mut reader)?,
+            unstable_names: Persist::read(&mut reader)?,
+            static_data: Persist::read(&mut reader)?,
+        };
+        let artifact = read_bytes(&mut reader)?;
+        // Safety: The artifact was written by `Self::store`, with an engine
+        // with the same compatibility hash. See the type level docs.
+        let module = unsafe { Module::deserialize(engine, artifact)? };
+
+        CompiledModule::from_parts(original, instrumented, module)
+    }
+
+    fn store(&self, path: &Path, instrumented: &Instrumented, module: &Module) -> Result<()> {
+        // Write to a temporary file and rename it, so other processes never see
+        // a partial entry.
+        let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        let result = Self::write_entry(&temp_path, instrumented, module)
+            .and_then(|()| Ok(fs::rename(&temp_path, path)?));
+
+        if result.is_err() {
+            // Don't leave partial entries behind. The original error is more
+            // useful than any error from removing the file.
+            let _ = fs::remove_file(&temp_path);
+        }
+
+        result
+    }
+
+    fn write_entry(path: &Path, instrumented: &Instrumented, module: &Module) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_bytes(&mut writer, &instrumented.module)?;
+        instrumented.unstable_names.write(&mut writer)?;
+        instrumented.static_data.write(&mut writer)?;
+        write_bytes(&mut writer, &module.serialize()?)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn cache_key(engine: &Engine, original: &[u8]) -> Hash {
+    let mut hasher = KeyHasher(Sha256::new());
+    hasher.write_u32(CACHE_VERSION);
+    hasher.write_usize(original.len());
+    hasher.write(original);
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    Hash::from_digest(hasher.0)
+}
+
+/// Feeds [`std::hash::Hash`] data into SHA-256, so keys are stable across
+/// builds of the host.
+struct KeyHasher(Sha256);
+
+impl Hasher for KeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+}
@@ -0,0 +1,195 @@
+//! Guests built as components, with the interface in `wit/codastraea.wit`.
+//!
+//! Components get the same host functions as core modules, but through
+//! generated bindings, rather than pointers into the guest's memory. They also
+//! get WASI preview 2. Snapshots aren't supported for components yet.
+use std::{
+    mem,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Result;
+use codastraea_server_api::NodeType;
+use wasmtime::{
+    component::{Component, Linker, ResourceTable},
+    Engine, Store,
+};
+use wasmtime_wasi::p2::{IoView, WasiCtx, WasiView};
+
+use crate::{
+    abi,
+    limits::Limits,
+    outcome::GuestReport,
+    runtime::{StoreData, WorkflowIndices, WorkflowKey},
+    thread::Thread,
+};
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit",
+        world: "guest",
+        // Unmatched `begin` and `end` calls trap, rather than panicking.
+        trappable_imports: ["fn-begin", "fn-end", "begin", "end"],
+    });
+}
+
+use bindings::codastraea::workflow::host::{Host, NodeKind};
+
+/// The WASM binary format preamble for components, as opposed to core
+/// modules.
+const COMPONENT_PREAMBLE: [u8; 8] = *b"\0asm\x0d\0\x01\0";
+
+/// Is `bytes` a component, rather than a core module?
+///
+/// `bytes` must be in the binary format.
+pub(crate) fn is_component(bytes: &[u8]) -> bool {
+    bytes.starts_with(&COMPONENT_PREAMBLE)
+}
+
+/// Host state for a component's imports.
+pub(crate) struct ComponentHost {
+    thread: Arc<RwLock<Thread>>,
+    workflow_indices: WorkflowIndices,
+    wasi: WasiCtx,
+    table: ResourceTable,
+    report: GuestReport,
+}
+
+impl ComponentHost {
+    pub fn new(
+        thread: Arc<RwLock<Thread>>,
+        workflow_indices: WorkflowIndices,
+        wasi: WasiCtx,
+    ) -> Self {
+        Self {
+            thread,
+            workflow_indices,
+            wasi,
+            table: ResourceTable::new(),
+            report: GuestReport::default(),
+        }
+    }
+
+    pub fn take_report(&mut self) -> GuestReport {
+        mem::take(&mut self.report)
+    }
+}
+
+impl Host for ComponentHost {
+    fn log(&mut self, message: String) {
+        println!("Log: {message}");
+    }
+
+    fn register_workflow_index(&mut self, module: String, name: String, index: u32) {
+        println!("Registering workflow index: {module}::{name} = {index}");
+        self.workflow_indices
+            .write()
+            .unwrap()
+            .insert(WorkflowKey::new(&module, &name), index);
+    }
+
+    fn fn_begin(&mut self, module: String, name: String) -> Result<()> {
+        println!("begin {module}::{name}");
+        self.thread.write().unwrap().begin(&NodeType::Call { name })
+    }
+
+    fn fn_end(&mut self, module: String, name: String) -> Result<()> {
+        println!("end {module}::{name}");
+        self.thread.write().unwrap().end(&NodeType::Call { name })
+    }
+
+    fn checkpoint_label(&mut self, label: String) {
+        self.report.checkpoint_label = Some(label);
+    }
+
+    fn return_value(&mut self, value: String) {
+        self.report.return_value = Some(value);
+    }
+
+    fn panic(&mut self, message: String) {
+        self.report.panic = Some(message);
+    }
+
+    fn begin(&mut self, node: NodeKind) -> Result<()> {
+        let node_type = node_type(node);
+        println!("begin {}", node_type.as_snake_str());
+        self.thread.write().unwrap().begin(&node_type)
+    }
+
+    fn end(&mut self, node: NodeKind) -> Result<()> {
+        let node_type = node_type(node);
+        println!("end {}", node_type.as_snake_str());
+        self.thread.write().unwrap().end(&node_type)
+    }
+}
+
+impl StoreData {
+    fn component_host(&mut self) -> &mut ComponentHost {
+        self.component
+            .as_mut()
+            .expect("Component imports should only be linked for components")
+    }
+}
+
+impl IoView for StoreData {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.component_host().table
+    }
+}
+
+impl WasiView for StoreData {
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.component_host().wasi
+    }
+}
+
+fn node_type(node: NodeKind) -> NodeType {
+    match node {
+        NodeKind::If => NodeType::If,
+        NodeKind::Condition => NodeType::Condition,
+        NodeKind::Then => NodeType::Then,
+        NodeKind::ElseIf => NodeType::ElseIf,
+        NodeKind::Else => NodeType::Else,
+    }
+}
+
+/// An instantiated component guest.
+pub(crate) struct ComponentGuest(bindings::Guest);
+
+impl ComponentGuest {
+    /// Compile and instantiate `original`, and check its ABI version.
+    ///
+    /// `store` must have a [`ComponentHost`].
+    pub fn new(
+        engine: &Engine,
+        store: &mut Store<StoreData>,
+        original: &[u8],
+        limits: &Limits,
+    ) -> Result<Self> {
+        let component = Component::new(engine, original)?;
+        let mut linker = Linker::new(engine);
+        // Components built for `wasm32-wasip2` import WASI, even if they don't
+        // use it.
+        wasmtime_wasi::p2::add_to_linker_sync(&mut linker)?;
+        bindings::Guest::add_to_linker(&mut linker, StoreData::component_host)?;
+        let guest = limits.call(store, |store| {
+            bindings::Guest::instantiate(store, &component, &linker)
+        })?;
+        let version = limits.call(store, |store| guest.call_abi_version(store))?;
+        abi::check_supported(version)?;
+
+        Ok(Self(guest))
+    }
+
+    pub fn register_workflows(&self, store: &mut Store<StoreData>) -> Result<u32> {
+        self.0.call_register_workflows(store)
+    }
+
+    pub fn init_workflow(&self, store: &mut Store<StoreData>, index: u32) -> Result<()> {
+        self.0.call_init_workflow(store, index)
+    }
+
+    pub fn run(&self, store: &mut Store<StoreData>) -> Result<bool> {
+        self.0.call_run(store)
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    fmt,
+    io::{Read, Write},
+};
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+use crate::snapshot::persist::Persist;
+
+/// Identifies the guest module a snapshot was taken from.
+///
+/// Snapshots refer to functions and globals by their exported names, and
+/// instrumentation names internal items by index. Restoring into a different
+/// module can silently corrupt the guest, so we record hashes of both the
+/// original and instrumented modules.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ModuleFingerprint {
+    original: Hash,
+    instrumented: Hash,
+}
+
+impl ModuleFingerprint {
+    pub fn new(original: &[u8], instrumented: &[u8]) -> Self {
+        Self {
+            original: Hash::new(original),
+            instrumented: Hash::new(instrumented),
+        }
+    }
+}
+
+impl fmt::Display for ModuleFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "original: {}, instrumented: {}",
+            self.original, self.instrumented
+        )
+    }
+}
+
+impl Persist for ModuleFingerprint {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.original.write(w)?;
+        self.instrumented.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            original: Persist::read(r)?,
+            instrumented: Persist::read(r)?,
+        })
+    }
+}
+
+/// A SHA-256 hash.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Hash([u8; 32]);
+
+impl Hash {
+    pub fn new(data: &[u8]) -> Self {
+        Self(Sha256::digest(data).into())
+    }
+
+    pub(crate) fn from_digest(digest: Sha256) -> Self {
+        Self(digest.finalize().into())
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Persist for Hash {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        w.write_all(&self.0)?;
+        Ok(())
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        let mut hash = [0; 32];
+        r.read_exact(&mut hash)?;
+        Ok(Self(hash))
+    }
+}
@@ -0,0 +1,181 @@
+//! Host resources passed to guests as `externref`s.
+//!
+//! Snapshots can't capture arbitrary host resources, so each resource is
+//! wrapped in a [`HostHandle`] with a stable [`HandleId`]. The snapshot records
+//! the ID, and restoring calls a factory from a [`HandleRegistry`] to recreate
+//! the resource.
+//!
+//! Only `externref`s can be host handles. Snapshots fail if a global or table
+//! contains a non-null `anyref`.
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use wasmtime::{AsContextMut, ExternRef, Rooted};
+
+use crate::snapshot::persist::Persist;
+
+/// Identifies a host resource, so it can be recreated on restore.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct HandleId {
+    kind: String,
+    id: u64,
+}
+
+impl HandleId {
+    pub fn new(kind: impl Into<String>, id: u64) -> Self {
+        Self {
+            kind: kind.into(),
+            id,
+        }
+    }
+
+    /// The kind of resource. This determines which factory is used to
+    /// recreate it.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl fmt::Display for HandleId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}#{}", self.kind, self.id)
+    }
+}
+
+impl Persist for HandleId {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.kind.write(w)?;
+        self.id.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            kind: Persist::read(r)?,
+            id: Persist::read(r)?,
+        })
+    }
+}
+
+/// A host resource, along with its [`HandleId`].
+///
+/// Only `externref`s created with [`HostHandle::into_extern_ref`] can be
+/// snapshotted.
+#[derive(Clone)]
+pub struct HostHandle {
+    id: HandleId,
+    resource: Arc<dyn Any + Send + Sync>,
+}
+
+impl HostHandle {
+    pub fn new(id: HandleId, resource: impl Any + Send + Sync) -> Self {
+        Self {
+            id,
+            resource: Arc::new(resource),
+        }
+    }
+
+    pub fn id(&self) -> &HandleId {
+        &self.id
+    }
+
+    /// Get the resource, if it's a `T`.
+    pub fn resource<T: Any>(&self) -> Option<&T> {
+        self.resource.downcast_ref()
+    }
+
+    pub fn into_extern_ref(self, ctx: impl AsContextMut) -> Result<Rooted<ExternRef>> {
+        ExternRef::new(ctx, self)
+    }
+
+    /// Get the handle from an `externref` created with
+    /// [`Self::into_extern_ref`].
+    pub fn from_extern_ref(ctx: impl AsContextMut, extern_ref: &ExternRef) -> Result<Self> {
+        extern_ref
+            .data(&ctx)?
+            .and_then(|data| data.downcast_ref::<Self>())
+            .cloned()
+            .context("`ExternRef` is not a `HostHandle`")
+    }
+}
+
+type Factory = dyn Fn(u64) -> Result<Arc<dyn Any + Send + Sync>> + Send + Sync;
+
+/// Factories to recreate host resources when restoring a snapshot.
+#[derive(Default)]
+pub struct HandleRegistry {
+    factories: HashMap<String, Box<Factory>>,
+}
+
+impl HandleRegistry {
+    /// Register a factory for resources of type `kind`.
+    ///
+    /// On restore, `factory` is called with the ID of each handle of this kind
+    /// in the snapshot.
+    pub fn register<T: Any + Send + Sync>(
+        &mut self,
+        kind: impl Into<String>,
+        factory: impl Fn(u64) -> Result<T> + Send + Sync + 'static,
+    ) {
+        self.factories.insert(
+            kind.into(),
+            Box::new(move |id| Ok(Arc::new(factory(id)?) as Arc<dyn Any + Send + Sync>)),
+        );
+    }
+
+    /// Recreate the resource for `id`.
+    pub fn recreate(&self, id: &HandleId) -> Result<HostHandle> {
+        let factory = self
+            .factories
+            .get(&id.kind)
+            .with_context(|| format!("No factory registered for handle kind '{}'", id.kind))?;
+
+        Ok(HostHandle {
+            id: id.clone(),
+            resource: factory(id.id).with_context(|| format!("Recreating handle {id}"))?,
+        })
+    }
+}
+
+/// Handles recreated during a restore.
+///
+/// Each resource is only recreated once, even if it's referenced from many
+/// places in the snapshot.
+pub(crate) struct RestoredHandles<'a> {
+    registry: &'a HandleRegistry,
+    handles: HashMap<HandleId, HostHandle>,
+}
+
+impl<'a> RestoredHandles<'a> {
+    pub fn new(registry: &'a HandleRegistry) -> Self {
+        Self {
+            registry,
+            handles: HashMap::new(),
+        }
+    }
+
+    pub fn extern_ref(
+        &mut self,
+        ctx: impl AsContextMut,
+        id: &HandleId,
+    ) -> Result<Rooted<ExternRef>> {
+        let handle = if let Some(handle) = self.handles.get(id) {
+            handle.clone()
+        } else {
+            let handle = self.registry.recreate(id)?;
+            self.handles.insert(id.clone(), handle.clone());
+            handle
+        };
+
+        handle.into_extern_ref(ctx)
+    }
+}
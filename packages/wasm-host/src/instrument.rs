@@ -1,69 +1,152 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use id_arena::Id;
-use walrus::{ExportItem, Function, Global, Memory, Module, ModuleExports, Result, Table};
+use walrus::{ir::Value, ConstExpr, DataKind, ExportItem, Module, ModuleExports, Result};
 
-pub fn instrument(wasm_module: &[u8]) -> Result<Vec<u8>> {
+use crate::snapshot::migrate::{DataSegment, StaticData};
+
+/// An instrumented module.
+pub struct Instrumented {
+    pub module: Vec<u8>,
+    /// Exported names of internal items that we couldn't give a stable name.
+    ///
+    /// These items have no name in the name section, or share a name with
+    /// another item of the same type, so they're named by their index.
+    /// Snapshots that refer to them will break if the guest is rebuilt with
+    /// items added or removed.
+    pub unstable_names: Vec<String>,
+    /// The module's active data segments.
+    pub static_data: StaticData,
+}
+
+/// Export all internal items, so snapshots can refer to them by name.
+///
+/// Items are named from the name section where possible, so their exported
+/// names survive rebuilds of the guest.
+pub fn instrument(wasm_module: &[u8]) -> Result<Instrumented> {
     let mut module = Module::from_buffer(wasm_module)?;
     let exports = &mut module.exports;
+    let mut unstable_names = Vec::new();
 
     export_internal_items(
         exports,
         "global",
-        module.globals.iter().filter(|g| g.mutable).map(Global::id),
+        module
+            .globals
+            .iter()
+            .filter(|g| g.mutable)
+            .map(|g| (g.id(), g.name.as_deref())),
         |item| match item {
             ExportItem::Global(id) => Some(id),
             _ => None,
         },
+        &mut unstable_names,
     );
 
     export_internal_items(
         exports,
         "memory",
-        module.memories.iter().map(Memory::id),
+        module.memories.iter().map(|m| (m.id(), m.name.as_deref())),
         |item| match item {
             ExportItem::Memory(id) => Some(id),
             _ => None,
         },
+        &mut unstable_names,
     );
 
     export_internal_items(
         exports,
         "table",
-        module.tables.iter().map(Table::id),
+        module.tables.iter().map(|t| (t.id(), t.name.as_deref())),
         |item| match item {
             ExportItem::Table(id) => Some(id),
             _ => None,
         },
+        &mut unstable_names,
     );
 
     export_internal_items(
         exports,
         "function",
-        module.funcs.iter().map(Function::id),
+        module.funcs.iter().map(|f| (f.id(), f.name.as_deref())),
         |item| match item {
             ExportItem::Function(id) => Some(id),
             _ => None,
         },
+        &mut unstable_names,
     );
 
-    Ok(module.emit_wasm())
+    Ok(Instrumented {
+        static_data: static_data(&module),
+        module: module.emit_wasm(),
+        unstable_names,
+    })
 }
 
-fn export_internal_items<T>(
+/// Find the active data segments in `module`, once all its memories are
+/// exported.
+fn static_data(module: &Module) -> StaticData {
+    let memory_names: HashMap<_, _> = module
+        .exports
+        .iter()
+        .filter_map(|export| match export.item {
+            ExportItem::Memory(id) => Some((id, &export.name)),
+            _ => None,
+        })
+        .collect();
+
+    let segments = module.data.iter().filter_map(|data| {
+        let DataKind::Active { memory, offset } = &data.kind else {
+            return None;
+        };
+        let offset = match offset {
+            ConstExpr::Value(Value::I32(offset)) => (*offset as u32).into(),
+            ConstExpr::Value(Value::I64(offset)) => *offset as u64,
+            _ => return None,
+        };
+
+        Some(DataSegment {
+            memory: memory_names.get(memory)?.to_string(),
+            offset,
+            data: data.value.clone(),
+        })
+    });
+
+    StaticData::new(segments.collect())
+}
+
+fn export_internal_items<'a, T: 'a>(
     exports: &mut ModuleExports,
     type_name: &str,
-    ids: impl Iterator<Item = Id<T>>,
+    items: impl Iterator<Item = (Id<T>, Option<&'a str>)>,
     into_id: impl Fn(ExportItem) -> Option<Id<T>>,
+    unstable_names: &mut Vec<String>,
 ) where
     Id<T>: Into<ExportItem>,
 {
     let exported_ids: HashSet<Id<T>> = exports.iter().filter_map(|e| into_id(e.item)).collect();
-    let internal_ids = ids.filter(|id| !exported_ids.contains(id));
+    let internal_items: Vec<_> = items.filter(|(id, _)| !exported_ids.contains(id)).collect();
+    let mut name_counts = HashMap::<&str, usize>::new();
+
+    for (_, name) in &internal_items {
+        if let Some(name) = name {
+            *name_counts.entry(name).or_default() += 1;
+        }
+    }
+
+    for (id, name) in internal_items {
+        let stable_name = name
+            .filter(|name| name_counts[name] == 1)
+            .map(|name| format!("__codastraea_{type_name}_name_{name}"))
+            .filter(|export_name| !exports.iter().any(|export| export.name == *export_name));
+
+        let export_name = stable_name.unwrap_or_else(|| {
+            let export_name = format!("__codastraea_{type_name}_{}", id.index());
+            unstable_names.push(export_name.clone());
+            export_name
+        });
 
-    for id in internal_ids {
-        let name = format!("__codastraea_{type_name}_{}", id.index());
-        exports.add(&name, id.into());
+        exports.add(&export_name, id.into());
     }
 }
 
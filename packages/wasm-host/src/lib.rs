@@ -1,4 +1,13 @@
+pub mod abi;
+pub mod cache;
+pub(crate) mod component;
+pub mod fingerprint;
+pub mod handle;
 pub mod instrument;
+pub mod limits;
+pub mod outcome;
+pub mod run;
 pub mod runtime;
 pub mod snapshot;
 pub mod thread;
+pub mod wasi;
@@ -0,0 +1,341 @@
+//! Limits on the resources a guest can use.
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, ensure, Result};
+use futures::FutureExt;
+use wasmtime::{Config, Engine, Instance, Module, ResourceLimiter, Store, Trap, UpdateDeadline};
+
+/// Limits for a [`Container`][crate::runtime::Container].
+///
+/// Exceeding a limit fails the guest call with a [`LimitExceeded`] error.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    /// The maximum size of each memory, in 64 KiB WASM pages.
+    pub max_memory_pages: Option<u64>,
+    /// The maximum number of elements in each table.
+    pub max_table_elements: Option<usize>,
+    /// The fuel available for each call into the guest.
+    ///
+    /// Roughly, each WASM instruction uses one unit of fuel. This gives
+    /// deterministic time slices, at the cost of slower execution.
+    pub fuel_per_call: Option<u64>,
+    /// The wall-clock time available for each call into the guest.
+    ///
+    /// This is checked every [`EPOCH_TICK`], so calls may overrun by up to
+    /// that much.
+    pub call_deadline: Option<Duration>,
+}
+
+/// How often we check [`Limits::call_deadline`].
+pub const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+const WASM_PAGE_SIZE: u64 = 0x10000;
+
+impl Limits {
+    /// Enable the engine features these limits need.
+    ///
+    /// Only needed for engines shared between containers. See
+    /// [`ContainerBuilder::engine`][crate::runtime::ContainerBuilder::engine].
+    pub fn configure(&self, config: &mut Config) {
+        config.consume_fuel(self.fuel_per_call.is_some());
+        config.epoch_interruption(self.call_deadline.is_some());
+    }
+
+    /// Check `engine` is configured for these limits, and get the
+    /// [`EpochTicker`] it needs, if any.
+    ///
+    /// Call deadlines and async support need a ticker.
+    pub(crate) fn prepare_engine(&self, engine: &Engine) -> Result<Option<EpochTicker>> {
+        if self.fuel_per_call.is_some() {
+            ensure!(
+                Store::new(engine, ()).set_fuel(0).is_ok(),
+                "`Limits::fuel_per_call` needs an engine that consumes fuel. See \
+                 `Limits::configure`."
+            );
+        }
+
+        if self.call_deadline.is_none() && !engine.is_async() {
+            return Ok(None);
+        }
+
+        Ok(Some(EpochTicker::for_engine(engine)?))
+    }
+
+    pub(crate) fn limiter(&self) -> Limiter {
+        Limiter {
+            max_memory_pages: self.max_memory_pages,
+            max_table_elements: self.max_table_elements,
+        }
+    }
+
+    /// Call into the guest, enforcing these limits.
+    pub(crate) fn call<T, R>(
+        &self,
+        store: &mut Store<T>,
+        call: impl FnOnce(&mut Store<T>) -> Result<R>,
+    ) -> Result<R> {
+        self.prepare_call(store)?;
+        call(store).map_err(|e| self.call_error(e))
+    }
+
+    /// Prepare `store` for an async call into the guest.
+    ///
+    /// As well as enforcing the limits, the guest yields to the async executor
+    /// every [`EPOCH_TICK`], so the engine must have epoch interruption
+    /// enabled. Pass the call to [`Self::enforce`].
+    pub(crate) fn prepare_async_call<T>(&self, store: &mut Store<T>) -> Result<()> {
+        self.reset_fuel(store)?;
+        // The executor might not resume the call straight after it yields, so
+        // count wall-clock time, rather than ticks.
+        let deadline = self
+            .call_deadline
+            .and_then(|deadline| Instant::now().checked_add(deadline));
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(move |_store| match deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(Trap::Interrupt.into()),
+            _ => Ok(UpdateDeadline::Yield(1)),
+        });
+
+        Ok(())
+    }
+
+    /// Await an async call into the guest, prepared with
+    /// [`Self::prepare_async_call`].
+    pub(crate) async fn enforce<R>(&self, call: impl Future<Output = Result<R>>) -> Result<R> {
+        call.await.map_err(|e| self.call_error(e))
+    }
+
+    /// Reset fuel and the deadline, ready for a call into the guest.
+    fn prepare_call<T>(&self, store: &mut Store<T>) -> Result<()> {
+        self.reset_fuel(store)?;
+
+        if let Some(ticks) = self.deadline_ticks() {
+            store.set_epoch_deadline(ticks);
+        }
+
+        Ok(())
+    }
+
+    fn reset_fuel<T>(&self, store: &mut Store<T>) -> Result<()> {
+        if let Some(fuel) = self.fuel_per_call {
+            store.set_fuel(fuel)?;
+        }
+
+        Ok(())
+    }
+
+    /// The number of epoch ticks until the deadline.
+    fn deadline_ticks(&self) -> Option<u64> {
+        self.call_deadline.map(|deadline| {
+            let ticks = deadline.as_nanos().div_ceil(EPOCH_TICK.as_nanos());
+            ticks.try_into().unwrap_or(u64::MAX)
+        })
+    }
+
+    /// Add a [`LimitExceeded`] context to `error`, if it was caused by running
+    /// out of fuel or time.
+    ///
+    /// Memory and table limit errors come from [`Limiter`], so they already
+    /// have one.
+    fn call_error(&self, error: anyhow::Error) -> anyhow::Error {
+        let limit = match error.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel) => self.fuel_per_call.map(|fuel| LimitExceeded::Fuel { fuel }),
+            Some(Trap::Interrupt) => self
+                .call_deadline
+                .map(|deadline| LimitExceeded::Deadline { deadline }),
+            _ => None,
+        };
+
+        match limit {
+            Some(limit) => error.context(limit),
+            None => error,
+        }
+    }
+}
+
+/// A guest exceeded one of its [`Limits`].
+///
+/// Use [`anyhow::Error::downcast_ref`] to find out which limit failed a call.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LimitExceeded {
+    Memory { max_pages: u64 },
+    Table { max_elements: usize },
+    Fuel { fuel: u64 },
+    Deadline { deadline: Duration },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Memory { max_pages } => {
+                write!(f, "Memory limit of {max_pages} pages exceeded")
+            }
+            Self::Table { max_elements } => {
+                write!(f, "Table limit of {max_elements} elements exceeded")
+            }
+            Self::Fuel { fuel } => write!(f, "Guest used all {fuel} units of fuel"),
+            Self::Deadline { deadline } => write!(f, "Guest call exceeded {deadline:?}"),
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
+/// Enforces [`Limits::max_memory_pages`] and [`Limits::max_table_elements`].
+pub(crate) struct Limiter {
+    max_memory_pages: Option<u64>,
+    max_table_elements: Option<usize>,
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        if let Some(max_pages) = self.max_memory_pages {
+            if u64::try_from(desired).map_or(true, |desired| {
+                desired > max_pages.saturating_mul(WASM_PAGE_SIZE)
+            }) {
+                return Err(LimitExceeded::Memory { max_pages }.into());
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        if let Some(max_elements) = self.max_table_elements {
+            if desired > max_elements {
+                return Err(LimitExceeded::Table { max_elements }.into());
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Increments an engine's epoch every [`EPOCH_TICK`], until every clone is
+/// dropped.
+///
+/// [`Limits::call_deadline`] needs exactly one ticker per engine, so there's
+/// one thread per engine, shared by every ticker from
+/// [`EpochTicker::for_engine`].
+#[derive(Clone)]
+pub struct EpochTicker {
+    _ticker: Arc<Ticker>,
+}
+
+/// Tickers that are still running, so each engine only has one.
+static TICKERS: Mutex<Vec<Weak<Ticker>>> = Mutex::new(Vec::new());
+
+impl EpochTicker {
+    /// The ticker for `engine`, which is started if it isn't running already.
+    ///
+    /// Fails if `engine` doesn't have epoch interruption enabled.
+    pub fn for_engine(engine: &Engine) -> Result<Self> {
+        let mut tickers = TICKERS.lock().unwrap();
+        tickers.retain(|ticker| ticker.strong_count() > 0);
+
+        let existing = tickers
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|ticker| Engine::same(&ticker.engine, engine));
+
+        if let Some(ticker) = existing {
+            return Ok(Self { _ticker: ticker });
+        }
+
+        ensure_epoch_interruption(engine)?;
+        let ticker = Arc::new(Ticker::start(engine));
+        tickers.push(Arc::downgrade(&ticker));
+        Ok(Self { _ticker: ticker })
+    }
+}
+
+/// Check `engine` has epoch interruption enabled.
+///
+/// Wasmtime doesn't expose an engine's config, so this calls a function after
+/// its deadline has passed, which only traps with epoch interruption.
+fn ensure_epoch_interruption(engine: &Engine) -> Result<()> {
+    let module = Module::new(engine, r#"(module (func (export "f")))"#)?;
+    let mut store = Store::new(engine, ());
+    // Make sure the call doesn't run out of fuel instead. This fails if the
+    // engine doesn't consume fuel, which is fine.
+    let _ = store.set_fuel(u64::MAX);
+    store.set_epoch_deadline(0);
+
+    let result = if engine.is_async() {
+        // The call never yields, so it's complete after one poll. We might
+        // already be in an executor, so we can't block on it.
+        async {
+            let instance = Instance::new_async(&mut store, &module, &[]).await?;
+            let f = instance.get_typed_func::<(), ()>(&mut store, "f")?;
+            f.call_async(&mut store, ()).await
+        }
+        .now_or_never()
+        .unwrap_or_else(|| Err(anyhow!("Checking for epoch interruption didn't complete")))
+    } else {
+        Instance::new(&mut store, &module, &[])
+            .and_then(|instance| instance.get_typed_func::<(), ()>(&mut store, "f"))
+            .and_then(|f| f.call(&mut store, ()))
+    };
+
+    match result {
+        Err(e) if e.downcast_ref::<Trap>() == Some(&Trap::Interrupt) => Ok(()),
+        Err(e) => Err(e),
+        Ok(()) => {
+            bail!("Call deadlines need an engine with epoch interruption. See `Limits::configure`.")
+        }
+    }
+}
+
+struct Ticker {
+    engine: Engine,
+    stop: Arc<AtomicBool>,
+}
+
+impl Ticker {
+    fn start(engine: &Engine) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        thread::spawn({
+            let engine = engine.clone();
+            let stop = stop.clone();
+
+            move || {
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(EPOCH_TICK);
+                    engine.increment_epoch();
+                }
+            }
+        });
+
+        Self {
+            engine: engine.clone(),
+            stop,
+        }
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,143 @@
+//! The result of running a workflow until it stops.
+use std::fmt;
+
+use wasmtime::{Trap, WasmBacktrace};
+
+use crate::limits::LimitExceeded;
+
+/// Why [`Container::run`][crate::runtime::Container::run] stopped.
+#[derive(Debug)]
+pub enum RunOutcome {
+    /// The workflow stopped at a checkpoint, and can be run again to continue.
+    Checkpoint {
+        /// The label the guest gave the checkpoint, if any.
+        label: Option<String>,
+    },
+    /// The workflow is complete.
+    Completed {
+        /// The workflow's return value, if the guest reported one.
+        return_value: Option<String>,
+    },
+    /// The guest trapped.
+    Trapped {
+        trap: Trap,
+        /// The guest's call stack when it trapped, if it was captured.
+        backtrace: Option<WasmBacktrace>,
+        /// The panic message, if the guest reported one before it trapped.
+        panic: Option<String>,
+    },
+    /// The guest exceeded one of its [`Limits`][crate::limits::Limits].
+    LimitExceeded(LimitExceeded),
+}
+
+impl RunOutcome {
+    /// Did the workflow stop at a checkpoint?
+    ///
+    /// If so, it can be run again to continue.
+    pub fn is_checkpoint(&self) -> bool {
+        matches!(self, Self::Checkpoint { .. })
+    }
+
+    /// Why the guest failed, if it did.
+    ///
+    /// This is brief, without the backtrace, so it can be shown in the call
+    /// tree.
+    pub fn error(&self) -> Option<String> {
+        match self {
+            Self::Checkpoint { .. } | Self::Completed { .. } => None,
+            Self::Trapped {
+                panic: Some(panic), ..
+            } => Some(format!("Guest panicked: {panic}")),
+            Self::Trapped { trap, .. } => Some(format!("Guest trapped: {trap}")),
+            Self::LimitExceeded(limit) => Some(limit.to_string()),
+        }
+    }
+
+    /// Classify an error from running the guest.
+    ///
+    /// Traps and limits become outcomes. Other errors, such as a guest misusing
+    /// a host import, are returned as they are.
+    pub(crate) fn from_error(error: anyhow::Error) -> anyhow::Result<Self> {
+        if let Some(limit) = error.downcast_ref::<LimitExceeded>() {
+            return Ok(Self::LimitExceeded(*limit));
+        }
+
+        let Some(trap) = error.downcast_ref::<Trap>().copied() else {
+            return Err(error);
+        };
+
+        Ok(Self::Trapped {
+            trap,
+            backtrace: error.downcast::<WasmBacktrace>().ok(),
+            panic: None,
+        })
+    }
+}
+
+impl fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Checkpoint { label: Some(label) } => write!(f, "Checkpoint '{label}'"),
+            Self::Checkpoint { label: None } => write!(f, "Checkpoint"),
+            Self::Completed {
+                return_value: Some(return_value),
+            } => write!(f, "Completed, returning {return_value}"),
+            Self::Completed { return_value: None } => write!(f, "Completed"),
+            Self::Trapped {
+                trap,
+                backtrace,
+                panic,
+            } => {
+                write!(f, "Guest trapped: {trap}")?;
+
+                if let Some(panic) = panic {
+                    write!(f, "\nPanic: {panic}")?;
+                }
+
+                if let Some(backtrace) = backtrace {
+                    write!(f, "\n{backtrace}")?;
+                }
+
+                Ok(())
+            }
+            Self::LimitExceeded(limit) => limit.fmt(f),
+        }
+    }
+}
+
+/// What the guest reported during a call to `run`, with
+/// `__codastraea_checkpoint_label`, `__codastraea_return_value` and
+/// `__codastraea_panic`.
+#[derive(Default)]
+pub(crate) struct GuestReport {
+    pub checkpoint_label: Option<String>,
+    pub return_value: Option<String>,
+    pub panic: Option<String>,
+}
+
+impl GuestReport {
+    /// The outcome of a call to `run`, which returned `result`.
+    ///
+    /// `result` is `true` if the guest stopped at a checkpoint. Errors are
+    /// classified with [`RunOutcome::from_error`].
+    pub fn outcome(self, result: anyhow::Result<bool>) -> anyhow::Result<RunOutcome> {
+        Ok(match result {
+            Ok(true) => RunOutcome::Checkpoint {
+                label: self.checkpoint_label,
+            },
+            Ok(false) => RunOutcome::Completed {
+                return_value: self.return_value,
+            },
+            Err(e) => match RunOutcome::from_error(e)? {
+                RunOutcome::Trapped {
+                    trap, backtrace, ..
+                } => RunOutcome::Trapped {
+                    trap,
+                    backtrace,
+                    panic: self.panic,
+                },
+                outcome => outcome,
+            },
+        })
+    }
+}
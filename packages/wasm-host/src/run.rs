@@ -0,0 +1,324 @@
+//! Run many workflows concurrently, from one compiled module.
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{ensure, Context, Result};
+pub use codastraea_server_api::{RunId, RunState};
+use futures::{executor::block_on, lock::Mutex as AsyncMutex, stream::BoxStream};
+use futures_channel::mpsc;
+use slotmap::SlotMap;
+
+use crate::{
+    cache::CompiledModule,
+    component,
+    outcome::RunOutcome,
+    runtime::{Container, ContainerBuilder},
+    thread::NodeStore,
+};
+
+/// Starts workflow runs from one compiled module, and tracks their state.
+///
+/// Each run has its own [`Container`], so different runs can be run at the
+/// same time, from different threads. This is cheap to clone, and clones share
+/// the same runs.
+#[derive(Clone)]
+pub struct RunManager(Arc<Shared>);
+
+struct Shared {
+    builder: ContainerBuilder,
+    module: CompiledModule,
+    runs: RwLock<SlotMap<RunId, Arc<Run>>>,
+}
+
+impl RunManager {
+    /// Compile `bytes`, a core module in the text or binary format, for runs
+    /// built with `builder`.
+    ///
+    /// Every run uses the same engine. If `builder` doesn't have one, it's
+    /// created here.
+    pub fn new(builder: ContainerBuilder, bytes: &[u8]) -> Result<Self> {
+        Self::with_engine(builder, bytes, false)
+    }
+
+    /// [`Self::new`], for runs with async support.
+    ///
+    /// Use [`Self::start_async`] and [`Self::run_async`] to start and run
+    /// workflows. See
+    /// [`ContainerBuilder::build_from_bytes_async`][crate::runtime::ContainerBuilder::build_from_bytes_async].
+    pub fn new_async(builder: ContainerBuilder, bytes: &[u8]) -> Result<Self> {
+        Self::with_engine(builder, bytes, true)
+    }
+
+    fn with_engine(builder: ContainerBuilder, bytes: &[u8], is_async: bool) -> Result<Self> {
+        let original = wat::parse_bytes(bytes)?;
+        ensure!(
+            !component::is_component(&original),
+            "Components aren't supported by `RunManager`"
+        );
+        let (engine, _epoch_ticker) = builder.engine_or_new(is_async)?;
+        let module = builder.compile(&engine, &original)?;
+
+        Ok(Self(Arc::new(Shared {
+            builder: builder.engine(&engine),
+            module,
+            runs: RwLock::default(),
+        })))
+    }
+
+    /// Start a run of the workflow `module::name`.
+    ///
+    /// The run is [`RunState::Queued`] until it's run with
+    /// [`Self::run_until_checkpoint`] or [`Self::spawn`].
+    pub fn start(&self, module: &str, name: &str) -> Result<RunId> {
+        let mut container = self.0.builder.build_from_module(&self.0.module)?;
+        container.register_workflows()?;
+        container.init_workflow(module, name)?;
+        Ok(self.insert(container))
+    }
+
+    /// [`Self::start`], for a `RunManager` with async support.
+    ///
+    /// The run is [`RunState::Queued`] until it's run with
+    /// [`Self::run_async`].
+    pub async fn start_async(&self, module: &str, name: &str) -> Result<RunId> {
+        let mut container = self
+            .0
+            .builder
+            .build_from_module_async(&self.0.module)
+            .await?;
+        container.register_workflows_async().await?;
+        container.init_workflow_async(module, name).await?;
+        Ok(self.insert(container))
+    }
+
+    fn insert(&self, container: Container) -> RunId {
+        let run = Run {
+            status: Mutex::new(RunStatus {
+                state: RunState::Queued,
+                watchers: Vec::new(),
+            }),
+            node_store: container.node_store(),
+            container: AsyncMutex::new(container),
+        };
+
+        self.0.runs.write().unwrap().insert(Arc::new(run))
+    }
+
+    /// Every run, along with its state.
+    pub fn runs(&self) -> Vec<(RunId, RunState)> {
+        self.0
+            .runs
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, run)| (id, run.state()))
+            .collect()
+    }
+
+    pub fn state(&self, id: RunId) -> Option<RunState> {
+        self.0.runs.read().unwrap().get(id).map(|run| run.state())
+    }
+
+    /// Watch the state of run `id`.
+    ///
+    /// The current state is sent first, then each change. The stream ends when
+    /// the run finishes, or is removed.
+    pub fn watch(&self, id: RunId) -> Option<BoxStream<'static, RunState>> {
+        let run = self.0.runs.read().unwrap().get(id).cloned()?;
+        Some(Box::pin(run.watch()))
+    }
+
+    /// The call tree for run `id`.
+    pub fn node_store(&self, id: RunId) -> Option<NodeStore> {
+        self.0
+            .runs
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|run| run.node_store.clone())
+    }
+
+    /// Stop tracking run `id`, and return its last state.
+    ///
+    /// If the run was spawned, it carries on until it finishes.
+    pub fn remove(&self, id: RunId) -> Option<RunState> {
+        self.0
+            .runs
+            .write()
+            .unwrap()
+            .remove(id)
+            .map(|run| run.state())
+    }
+
+    /// Run `id` until its next checkpoint, and return its new state.
+    ///
+    /// Errors from the guest are reported as [`RunState::Failed`], rather than
+    /// failing this call. If the run has already finished, this just returns
+    /// its state.
+    pub fn run_until_checkpoint(&self, id: RunId) -> Result<RunState> {
+        self.ensure_async(false)?;
+        Ok(self.run(id)?.run_until_checkpoint())
+    }
+
+    /// Run `id` until it finishes, on a new thread.
+    ///
+    /// The thread returns the run's final state.
+    pub fn spawn(&self, id: RunId) -> Result<JoinHandle<RunState>> {
+        self.ensure_async(false)?;
+        let run = self.run(id)?;
+
+        Ok(thread::spawn(move || loop {
+            let state = run.run_until_checkpoint();
+
+            if !matches!(state, RunState::Paused { .. }) {
+                return state;
+            }
+        }))
+    }
+
+    /// Run `id` until it finishes, for a `RunManager` with async support, and
+    /// return its final state.
+    ///
+    /// The guest yields to the async executor every
+    /// [`EPOCH_TICK`][crate::limits::EPOCH_TICK], so many runs can share a
+    /// thread. Use this in place of [`Self::spawn`].
+    pub async fn run_async(&self, id: RunId) -> Result<RunState> {
+        self.ensure_async(true)?;
+        let run = self.run(id)?;
+
+        loop {
+            let state = run.run_until_checkpoint_async().await;
+
+            if !matches!(state, RunState::Paused { .. }) {
+                return Ok(state);
+            }
+        }
+    }
+
+    /// Check this was created with [`Self::new_async`] if, and only if,
+    /// `is_async`.
+    fn ensure_async(&self, is_async: bool) -> Result<()> {
+        if is_async {
+            ensure!(
+                self.0.module.engine().is_async(),
+                "Use `RunManager::new_async` to run workflows asynchronously"
+            );
+        } else {
+            ensure!(
+                !self.0.module.engine().is_async(),
+                "Use `RunManager::run_async` for a `RunManager` with async support"
+            );
+        }
+
+        Ok(())
+    }
+
+    fn run(&self, id: RunId) -> Result<Arc<Run>> {
+        self.0
+            .runs
+            .read()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .with_context(|| format!("Unknown run {id:?}"))
+    }
+}
+
+struct Run {
+    status: Mutex<RunStatus>,
+    node_store: NodeStore,
+    /// This is held for the whole of each call into the guest, so calls for
+    /// this run don't overlap. Async calls hold it across `await`s.
+    container: AsyncMutex<Container>,
+}
+
+impl Run {
+    fn state(&self) -> RunState {
+        self.status.lock().unwrap().state.clone()
+    }
+
+    fn watch(&self) -> mpsc::UnboundedReceiver<RunState> {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut status = self.status.lock().unwrap();
+        sender.unbounded_send(status.state.clone()).unwrap();
+
+        if !status.state.is_finished() {
+            status.watchers.push(sender);
+        }
+
+        receiver
+    }
+
+    fn run_until_checkpoint(&self) -> RunState {
+        let mut container = block_on(self.container.lock());
+
+        if let Some(state) = self.begin() {
+            return state;
+        }
+
+        self.finish(container.run())
+    }
+
+    async fn run_until_checkpoint_async(&self) -> RunState {
+        let mut container = self.container.lock().await;
+
+        if let Some(state) = self.begin() {
+            return state;
+        }
+
+        self.finish(container.run_async().await)
+    }
+
+    /// Mark the run as running, unless it's finished, in which case return its
+    /// final state.
+    fn begin(&self) -> Option<RunState> {
+        let mut status = self.status.lock().unwrap();
+
+        if status.state.is_finished() {
+            return Some(status.state.clone());
+        }
+
+        status.set(RunState::Running);
+        None
+    }
+
+    /// Record the state after a call to the guest's `run`.
+    fn finish(&self, outcome: Result<RunOutcome>) -> RunState {
+        let state = match outcome {
+            Ok(RunOutcome::Checkpoint { label }) => RunState::Paused { label },
+            Ok(RunOutcome::Completed { return_value }) => RunState::Completed { return_value },
+            Ok(outcome) => RunState::Failed {
+                error: outcome.to_string(),
+            },
+            Err(e) => RunState::Failed {
+                error: format!("{e:#}"),
+            },
+        };
+
+        self.status.lock().unwrap().set(state.clone());
+        state
+    }
+}
+
+/// A run's state, and the channels watching it.
+struct RunStatus {
+    state: RunState,
+    watchers: Vec<mpsc::UnboundedSender<RunState>>,
+}
+
+impl RunStatus {
+    /// Set the state, and tell the watchers. Watchers are dropped once the run
+    /// finishes, which ends their streams.
+    fn set(&mut self, state: RunState) {
+        self.watchers
+            .retain(|watcher| watcher.unbounded_send(state.clone()).is_ok());
+
+        if state.is_finished() {
+            self.watchers.clear();
+        }
+
+        self.state = state;
+    }
+}
@@ -1,41 +1,86 @@
 use std::{
     collections::HashMap,
     fs,
+    io::{Read, Write},
+    mem,
     path::Path,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use clonelet::clone;
 use codastraea_server_api::NodeType;
-use wasmtime::{Caller, Engine, Extern, Instance, Linker, Module, ModuleExport, Store, TypedFunc};
+use wasmtime::{Caller, Config, Engine, Extern, Instance, Linker, ModuleExport, Store, TypedFunc};
+use wasmtime_wasi::preview1::WasiP1Ctx;
 
 use crate::{
-    instrument::instrument,
-    snapshot::Snapshot,
-    thread::{NodeStore, Thread},
+    abi::{self, LEGACY_LINKER_MODULE, LINKER_MODULE, TRACED_NODE_TYPES},
+    cache::{CompiledModule, ModuleCache},
+    component::{self, ComponentGuest, ComponentHost},
+    fingerprint::ModuleFingerprint,
+    handle::HandleRegistry,
+    limits::{EpochTicker, Limiter, Limits},
+    outcome::{GuestReport, RunOutcome},
+    snapshot::{
+        chain::SnapshotChain,
+        migrate::{MemoryLayout, MigrationReport, StaticData},
+        persist::{read_header, write_header, Persist, SnapshotKind},
+        Snapshot, SnapshotOptions,
+    },
+    thread::{NodeStore, Thread, ThreadSnapshot},
+    wasi::{Wasi, WasiOptions, WasiSnapshot},
 };
 
+/// Host state for a container's [`Store`].
+///
+/// Extra imports defined with [`ContainerBuilder::imports`] get this as their
+/// [`Caller`] data.
+pub struct StoreData {
+    limiter: Limiter,
+    pub(crate) wasi: Option<WasiP1Ctx>,
+    pub(crate) component: Option<ComponentHost>,
+    report: GuestReport,
+}
+
+impl StoreData {
+    /// Take what the guest reported during the last call to `run`.
+    fn take_report(&mut self) -> GuestReport {
+        match &mut self.component {
+            Some(component) => component.take_report(),
+            None => mem::take(&mut self.report),
+        }
+    }
+}
+
+/// Runs a guest, which can be a core module or a component.
+///
+/// Core modules use the ABI in [`abi`], and components use the WIT interface
+/// in `wit/codastraea.wit`. Snapshots are only supported for core modules.
 pub struct Container {
-    instance: Instance,
-    store: Store<()>,
-    register_workflows: TypedFunc<(), u32>,
-    init_workflow: TypedFunc<u32, ()>,
-    run: TypedFunc<(), i32>,
+    guest: Guest,
+    store: Store<StoreData>,
     thread: Arc<RwLock<Thread>>,
     workflow_indices: WorkflowIndices,
+    fingerprint: ModuleFingerprint,
+    layout: MemoryLayout,
+    static_data: StaticData,
+    snapshot_options: SnapshotOptions,
+    handles: HandleRegistry,
+    limits: Limits,
+    _epoch_ticker: Option<EpochTicker>,
+    wasi: Option<Wasi>,
 }
 
-type WorkflowIndices = Arc<RwLock<HashMap<WorkflowKey, u32>>>;
+pub(crate) type WorkflowIndices = Arc<RwLock<HashMap<WorkflowKey, u32>>>;
 
 #[derive(Hash, Eq, PartialEq)]
-struct WorkflowKey {
+pub(crate) struct WorkflowKey {
     module: String,
     name: String,
 }
 
 impl WorkflowKey {
-    fn new(module: &str, name: &str) -> Self {
+    pub fn new(module: &str, name: &str) -> Self {
         Self {
             module: module.to_string(),
             name: name.to_string(),
@@ -43,97 +88,947 @@ impl WorkflowKey {
     }
 }
 
-impl Container {
-    pub fn from_file(wat_file: &Path) -> Result<Self> {
-        let wat = fs::read(wat_file).context(format!("Opening file {wat_file:?}"))?;
-        let wat = instrument(&wat)?;
-        let engine = Engine::default();
-        let module = Module::new(&engine, wat)?;
+/// The engines a [`ContainerBuilder`] creates, when it doesn't have a shared
+/// engine.
+#[derive(Default)]
+struct DefaultEngines {
+    sync: Mutex<Option<Engine>>,
+    async_support: Mutex<Option<Engine>>,
+}
+
+/// A function that defines extra imports for core module guests.
+type DefineImports = Arc<dyn Fn(&mut Linker<StoreData>) -> Result<()> + Send + Sync>;
+
+/// Builds [`Container`]s.
+///
+/// One builder can build any number of containers, from module bytes or a
+/// [`CompiledModule`].
+#[derive(Clone, Default)]
+pub struct ContainerBuilder {
+    engine: Option<Engine>,
+    /// Engines created by this builder, shared with its clones.
+    default_engines: Arc<DefaultEngines>,
+    cache: Option<ModuleCache>,
+    limits: Limits,
+    wasi: Option<WasiOptions>,
+    imports: Vec<DefineImports>,
+}
+
+impl ContainerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `engine` for containers.
+    ///
+    /// Otherwise, the builder creates an engine when it's first needed, and
+    /// shares it between the containers it builds, and its clones. There's
+    /// one engine for the async build methods, and one for the others.
+    ///
+    /// `engine` must be configured for the [`Limits`], with
+    /// [`Limits::configure`], or building containers fails. Containers keep
+    /// an [`EpochTicker`] running for `engine` if they need one.
+    ///
+    /// The async build methods need an engine with async support and epoch
+    /// interruption. The other build methods need an engine without async
+    /// support.
+    pub fn engine(mut self, engine: &Engine) -> Self {
+        self.engine = Some(engine.clone());
+        self
+    }
+
+    /// Cache instrumented and compiled modules here, so creating containers
+    /// for the same module is cheap.
+    ///
+    /// Components aren't cached.
+    pub fn cache(mut self, cache: ModuleCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Limits on the resources the guest can use.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        // The engines were configured for the old limits.
+        self.default_engines = Arc::default();
+        self
+    }
+
+    /// Provide WASI preview 1 imports to core module guests.
+    ///
+    /// Components always get WASI preview 2 imports, with these options, or
+    /// the defaults.
+    pub fn wasi(mut self, options: WasiOptions) -> Self {
+        self.wasi = Some(options);
+        self
+    }
+
+    /// Define extra imports for the guest with `define`.
+    ///
+    /// `define` is called for each container, after the host's own imports
+    /// are defined. This isn't supported for components.
+    pub fn imports(
+        mut self,
+        define: impl Fn(&mut Linker<StoreData>) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.imports.push(Arc::new(define));
+        self
+    }
+
+    /// Build a container from a module or component in a file, in the text or
+    /// binary format.
+    pub fn build_from_file(&self, path: &Path) -> Result<Container> {
+        let bytes = fs::read(path).with_context(|| format!("Opening file {path:?}"))?;
+        self.build_from_bytes(&bytes)
+            .with_context(|| format!("Loading {path:?}"))
+    }
+
+    /// Build a container from a module or component, in the text or binary
+    /// format.
+    pub fn build_from_bytes(&self, bytes: &[u8]) -> Result<Container> {
+        // Instrumentation needs the binary format.
+        let original = wat::parse_bytes(bytes)?;
+        let (engine, epoch_ticker) = self.engine_or_new(false)?;
+
+        if component::is_component(&original) {
+            return self.build(engine, epoch_ticker, GuestCode::Component(&original));
+        }
+
+        let module = self.compile(&engine, &original)?;
+        self.build(engine, epoch_ticker, GuestCode::Module(&module))
+    }
+
+    /// Build a container from a module that's already compiled.
+    ///
+    /// The module's engine is used, so see [`Self::engine`] for its
+    /// requirements.
+    pub fn build_from_module(&self, module: &CompiledModule) -> Result<Container> {
+        let engine = module.engine();
+
+        if let Some(shared_engine) = &self.engine {
+            ensure!(
+                Engine::same(engine, shared_engine),
+                "Module was compiled with a different engine"
+            );
+        }
+
+        let epoch_ticker = self.limits.prepare_engine(engine)?;
+        self.build(engine.clone(), epoch_ticker, GuestCode::Module(module))
+    }
+
+    /// Build a container from a module, in the text or binary format, with
+    /// async support.
+    ///
+    /// Calls into the guest yield to the async executor every [`EPOCH_TICK`],
+    /// so many containers can share a thread. Extra imports can be async. See
+    /// [`Linker::func_wrap_async`].
+    ///
+    /// Components aren't supported.
+    pub async fn build_from_bytes_async(&self, bytes: &[u8]) -> Result<Container> {
+        let original = wat::parse_bytes(bytes)?;
+        ensure!(
+            !component::is_component(&original),
+            "Async support isn't available for components"
+        );
+        let (engine, epoch_ticker) = self.engine_or_new(true)?;
+        let module = self.compile(&engine, &original)?;
+        self.build_async(engine, epoch_ticker, &module).await
+    }
+
+    /// Build a container with async support, from a module that's already
+    /// compiled.
+    ///
+    /// See [`Self::build_from_bytes_async`] and [`Self::build_from_module`].
+    pub async fn build_from_module_async(&self, module: &CompiledModule) -> Result<Container> {
+        let engine = module.engine();
+
+        if let Some(shared_engine) = &self.engine {
+            ensure!(
+                Engine::same(engine, shared_engine),
+                "Module was compiled with a different engine"
+            );
+        }
+
+        let epoch_ticker = self.limits.prepare_engine(engine)?;
+        self.build_async(engine.clone(), epoch_ticker, module).await
+    }
+
+    /// The shared engine, or a new engine configured for the limits, along
+    /// with the epoch ticker it needs.
+    pub(crate) fn engine_or_new(&self, is_async: bool) -> Result<(Engine, Option<EpochTicker>)> {
+        let engine = match &self.engine {
+            Some(engine) => engine.clone(),
+            None => self.default_engine(is_async)?,
+        };
+
+        let epoch_ticker = self.limits.prepare_engine(&engine)?;
+        Ok((engine, epoch_ticker))
+    }
+
+    /// This builder's engine for the async build methods, or the other build
+    /// methods, configured for the limits.
+    fn default_engine(&self, is_async: bool) -> Result<Engine> {
+        let engines = &self.default_engines;
+        let mut engine = if is_async {
+            engines.async_support.lock().unwrap()
+        } else {
+            engines.sync.lock().unwrap()
+        };
+
+        if let Some(engine) = &*engine {
+            return Ok(engine.clone());
+        }
+
+        let mut config = Config::new();
+        self.limits.configure(&mut config);
+
+        if is_async {
+            // Async calls yield to the executor on each epoch tick.
+            config.async_support(true).epoch_interruption(true);
+        }
+
+        let new_engine = Engine::new(&config)?;
+        *engine = Some(new_engine.clone());
+        Ok(new_engine)
+    }
+
+    /// Compile `original`, which must be a core module in the binary format,
+    /// using the cache if there is one.
+    pub(crate) fn compile(&self, engine: &Engine, original: &[u8]) -> Result<CompiledModule> {
+        match &self.cache {
+            Some(cache) => cache.get_or_compile(engine, original),
+            None => CompiledModule::new(engine, original),
+        }
+    }
+
+    fn build(
+        &self,
+        engine: Engine,
+        epoch_ticker: Option<EpochTicker>,
+        code: GuestCode,
+    ) -> Result<Container> {
+        ensure!(
+            !engine.is_async(),
+            "Engines with async support need the async build methods"
+        );
+        let limits = &self.limits;
+
+        match code {
+            GuestCode::Component(original) => {
+                ensure!(
+                    self.imports.is_empty(),
+                    "Extra imports aren't supported for components"
+                );
+                // Components always get WASI preview 2, as `wasm32-wasip2` builds
+                // import it.
+                let (wasi, wasi_ctx) = Wasi::new_p2(&self.wasi.clone().unwrap_or_default())?;
+                let mut parts = self.parts(&engine, Some(wasi), None);
+                let host = ComponentHost::new(
+                    parts.thread.clone(),
+                    parts.workflow_indices.clone(),
+                    wasi_ctx,
+                );
+                parts.store.data_mut().component = Some(host);
+                let guest = ComponentGuest::new(&engine, &mut parts.store, original, limits)?;
+                // Components aren't instrumented, and can't be snapshotted.
+                let fingerprint = ModuleFingerprint::new(original, original);
+                Ok(parts.finish(
+                    Guest::Component(guest),
+                    fingerprint,
+                    StaticData::default(),
+                    limits,
+                    epoch_ticker,
+                ))
+            }
+            GuestCode::Module(module) => {
+                let mut parts = self.module_parts(&engine)?;
+                let linker = self.module_linker(&engine, &parts, module)?;
+                let store = &mut parts.store;
+                let instance =
+                    limits.call(store, |store| linker.instantiate(store, &module.module))?;
+                let version = limits.call(store, |store| abi::check_version(store, &instance))?;
+                abi::check_declared(module.abi_version, version)?;
+                let guest = ModuleGuest::new(store, instance)?;
+                Ok(parts.finish(
+                    Guest::Module(guest),
+                    module.fingerprint,
+                    module.static_data.clone(),
+                    limits,
+                    epoch_ticker,
+                ))
+            }
+        }
+    }
+
+    async fn build_async(
+        &self,
+        engine: Engine,
+        epoch_ticker: Option<EpochTicker>,
+        module: &CompiledModule,
+    ) -> Result<Container> {
+        ensure!(
+            engine.is_async(),
+            "The async build methods need an engine with async support"
+        );
+        let limits = &self.limits;
+        let mut parts = self.module_parts(&engine)?;
+        let linker = self.module_linker(&engine, &parts, module)?;
+        let store = &mut parts.store;
+        limits.prepare_async_call(store)?;
+        let instance = limits
+            .enforce(linker.instantiate_async(&mut *store, &module.module))
+            .await?;
+        limits.prepare_async_call(store)?;
+        let version = limits
+            .enforce(abi::check_version_async(&mut *store, &instance))
+            .await?;
+        abi::check_declared(module.abi_version, version)?;
+        let guest = ModuleGuest::new(store, instance)?;
+
+        Ok(parts.finish(
+            Guest::Module(guest),
+            module.fingerprint,
+            module.static_data.clone(),
+            limits,
+            epoch_ticker,
+        ))
+    }
+
+    /// Create the host state for a new container with a core module guest.
+    fn module_parts(&self, engine: &Engine) -> Result<ContainerParts> {
+        Ok(match &self.wasi {
+            Some(wasi_options) => {
+                let (wasi, wasi_ctx) = Wasi::new(wasi_options)?;
+                self.parts(engine, Some(wasi), Some(wasi_ctx))
+            }
+            None => self.parts(engine, None, None),
+        })
+    }
+
+    /// Create the host state for a new container.
+    fn parts(
+        &self,
+        engine: &Engine,
+        wasi: Option<Wasi>,
+        wasi_ctx: Option<WasiP1Ctx>,
+    ) -> ContainerParts {
+        let mut store = Store::new(
+            engine,
+            StoreData {
+                limiter: self.limits.limiter(),
+                wasi: wasi_ctx,
+                component: None,
+                report: GuestReport::default(),
+            },
+        );
+        store.limiter(|data| &mut data.limiter);
+
+        ContainerParts {
+            store,
+            thread: Arc::new(RwLock::new(Thread::empty())),
+            workflow_indices: WorkflowIndices::default(),
+            wasi,
+        }
+    }
+
+    /// A linker with the host's imports, and any extra imports, for `module`.
+    fn module_linker(
+        &self,
+        engine: &Engine,
+        parts: &ContainerParts,
+        module: &CompiledModule,
+    ) -> Result<Linker<StoreData>> {
+        // Check the version first, so a guest built for a newer ABI gets a
+        // version error, rather than errors about its imports.
+        if let Some(version) = module.abi_version {
+            abi::check_supported(version)?;
+        }
+
+        let module = &module.module;
+        abi::validate(module)?;
 
         let Some(memory_export) = module.get_export_index("memory") else {
             bail!("failed to find `memory` export in module");
         };
-        let linker = &mut Linker::new(&engine);
-        let workflow_indices = WorkflowIndices::default();
-        define_register_workflow_index(workflow_indices.clone(), linker, memory_export)?;
-        define_log(linker, memory_export)?;
-        let thread = Arc::new(RwLock::new(Thread::empty()));
-        define_trace_fn("begin", Thread::begin, &thread, linker, memory_export)?;
-        define_trace_fn("end", Thread::end, &thread, linker, memory_export)?;
-
-        for node_type in [
-            NodeType::If,
-            NodeType::Condition,
-            NodeType::Then,
-            NodeType::ElseIf,
-            NodeType::Else,
-        ] {
-            define_trace("begin", Thread::begin, &thread, linker, &node_type)?;
-            define_trace("end", Thread::end, &thread, linker, &node_type)?;
-        }
-
-        let mut store = Store::new(&engine, ());
-        let instance = linker.instantiate(&mut store, &module)?;
-        let register_workflows =
-            instance.get_typed_func(&mut store, "__codastraea_register_workflows")?;
-        let init_workflow = instance.get_typed_func(&mut store, "__codastraea_init_workflow")?;
-        let run = instance.get_typed_func(&mut store, "__codastraea_run")?;
+        let mut linker = Linker::new(engine);
+        let thread = &parts.thread;
+        define_register_workflow_index(parts.workflow_indices.clone(), &mut linker, memory_export)?;
+        define_log(&mut linker, memory_export)?;
+        define_report(
+            "checkpoint_label",
+            |report, label| report.checkpoint_label = Some(label),
+            &mut linker,
+            memory_export,
+        )?;
+        define_report(
+            "return_value",
+            |report, value| report.return_value = Some(value),
+            &mut linker,
+            memory_export,
+        )?;
+        define_report(
+            "panic",
+            |report, message| report.panic = Some(message),
+            &mut linker,
+            memory_export,
+        )?;
+        define_trace_fn("begin", Thread::begin, thread, &mut linker, memory_export)?;
+        define_trace_fn("end", Thread::end, thread, &mut linker, memory_export)?;
 
-        Ok(Self {
-            instance,
-            store,
-            register_workflows,
-            init_workflow,
-            run,
-            thread,
-            workflow_indices,
+        for node_type in &TRACED_NODE_TYPES {
+            define_trace("begin", Thread::begin, thread, &mut linker, node_type)?;
+            define_trace("end", Thread::end, thread, &mut linker, node_type)?;
+        }
+
+        linker.alias_module(LINKER_MODULE, LEGACY_LINKER_MODULE)?;
+
+        if parts.wasi.is_some() {
+            Wasi::add_to_linker(&mut linker, engine.is_async())?;
+        }
+
+        for define in &self.imports {
+            define(&mut linker)?;
+        }
+
+        Ok(linker)
+    }
+}
+
+/// The host state for a container, before its guest is instantiated.
+struct ContainerParts {
+    store: Store<StoreData>,
+    thread: Arc<RwLock<Thread>>,
+    workflow_indices: WorkflowIndices,
+    wasi: Option<Wasi>,
+}
+
+impl ContainerParts {
+    fn finish(
+        mut self,
+        guest: Guest,
+        fingerprint: ModuleFingerprint,
+        static_data: StaticData,
+        limits: &Limits,
+        epoch_ticker: Option<EpochTicker>,
+    ) -> Container {
+        let layout = match &guest {
+            Guest::Module(guest) => {
+                MemoryLayout::new(&mut self.store, &guest.instance, &static_data)
+            }
+            Guest::Component(_) => MemoryLayout::default(),
+        };
+
+        Container {
+            guest,
+            store: self.store,
+            thread: self.thread,
+            workflow_indices: self.workflow_indices,
+            fingerprint,
+            layout,
+            static_data,
+            snapshot_options: SnapshotOptions::default(),
+            handles: HandleRegistry::default(),
+            limits: limits.clone(),
+            _epoch_ticker: epoch_ticker,
+            wasi: self.wasi,
+        }
+    }
+}
+
+/// The code for a new container's guest.
+enum GuestCode<'a> {
+    Module(&'a CompiledModule),
+    /// A component, in the binary format.
+    Component(&'a [u8]),
+}
+
+impl Container {
+    /// Build a container with the default options.
+    ///
+    /// See [`ContainerBuilder`].
+    pub fn from_file(wat_file: &Path) -> Result<Self> {
+        ContainerBuilder::new().build_from_file(wat_file)
+    }
+
+    /// The guest's WASI workspace, if it has one. See
+    /// [`WasiOptions::workspaces`].
+    pub fn workspace(&self) -> Option<&Path> {
+        self.wasi.as_ref().and_then(Wasi::workspace)
+    }
+
+    /// Everything the guest has written to stdout, if WASI is enabled.
+    pub fn stdout(&self) -> Option<Vec<u8>> {
+        self.wasi.as_ref().map(Wasi::stdout)
+    }
+
+    /// Everything the guest has written to stderr, if WASI is enabled.
+    pub fn stderr(&self) -> Option<Vec<u8>> {
+        self.wasi.as_ref().map(Wasi::stderr)
+    }
+
+    pub fn fingerprint(&self) -> ModuleFingerprint {
+        self.fingerprint
+    }
+
+    /// Factories used to recreate host handles when restoring a snapshot.
+    pub fn handles_mut(&mut self) -> &mut HandleRegistry {
+        &mut self.handles
+    }
+
+    /// Set the options used for future snapshots.
+    pub fn set_snapshot_options(&mut self, options: SnapshotOptions) {
+        self.snapshot_options = options;
+    }
+
+    pub fn snapshot(&mut self) -> Result<ContainerSnapshot> {
+        let instance = self.guest.instance()?;
+
+        Ok(ContainerSnapshot {
+            fingerprint: self.fingerprint,
+            layout: self.layout.clone(),
+            wasm: Snapshot::with_options(&mut self.store, &instance, &self.snapshot_options)?,
+            thread: self.thread.read().unwrap().snapshot(),
+            wasi: self.wasi.as_ref().map(Wasi::snapshot),
         })
     }
 
-    pub fn snapshot(&mut self) -> Result<Snapshot> {
-        // TODO: We also need to snapshot and restore the call stack.
-        Snapshot::new(&mut self.store, &self.instance)
+    /// Start a [`ContainerSnapshotChain`] with a full snapshot.
+    pub fn snapshot_chain(&mut self) -> Result<ContainerSnapshotChain> {
+        let instance = self.guest.instance()?;
+
+        Ok(ContainerSnapshotChain {
+            fingerprint: self.fingerprint,
+            layout: self.layout.clone(),
+            wasm: SnapshotChain::with_options(
+                &mut self.store,
+                &instance,
+                self.snapshot_options.clone(),
+            )?,
+            threads: vec![self.thread.read().unwrap().snapshot()],
+            wasi: vec![self.wasi.as_ref().map(Wasi::snapshot)],
+        })
     }
 
-    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
-        snapshot.restore(&mut self.store, &self.instance)
+    /// Add an incremental snapshot to the end of `chain`.
+    pub fn push_snapshot(&mut self, chain: &mut ContainerSnapshotChain) -> Result<()> {
+        self.check_fingerprint(chain.fingerprint)?;
+        let instance = self.guest.instance()?;
+        chain.wasm.push(&mut self.store, &instance)?;
+        chain.threads.push(self.thread.read().unwrap().snapshot());
+        chain.wasi.push(self.wasi.as_ref().map(Wasi::snapshot));
+        Ok(())
+    }
+
+    /// Restore `snapshot` into this container.
+    ///
+    /// This fails if `snapshot` was taken from a different module. See
+    /// [`Self::restore_ignoring_fingerprint`].
+    pub fn restore(&mut self, snapshot: &ContainerSnapshot) -> Result<()> {
+        self.check_fingerprint(snapshot.fingerprint)?;
+        self.restore_ignoring_fingerprint(snapshot)
+    }
+
+    /// Restore `snapshot`, even if it was taken from a different module.
+    ///
+    /// Functions and globals are looked up by their exported names, so this
+    /// will corrupt the guest unless the modules are compatible.
+    pub fn restore_ignoring_fingerprint(&mut self, snapshot: &ContainerSnapshot) -> Result<()> {
+        ensure!(
+            snapshot.wasi.is_some() == self.wasi.is_some(),
+            "WASI must be enabled for both the snapshot and the container, or neither"
+        );
+        let instance = self.guest.instance()?;
+        snapshot
+            .wasm
+            .restore_with_handles(&mut self.store, &instance, &self.handles)?;
+
+        if let (Some(wasi), Some(wasi_snapshot)) = (&self.wasi, &snapshot.wasi) {
+            wasi.restore(wasi_snapshot);
+        }
+
+        self.thread.write().unwrap().restore(&snapshot.thread)
+    }
+
+    /// Check whether `snapshot` can be restored into this container, even if
+    /// it was taken from a different build of the guest.
+    ///
+    /// Items are matched up by their exported names, so this relies on the
+    /// guest having a name section. See
+    /// [`Instrumented::unstable_names`][crate::instrument::Instrumented::unstable_names].
+    pub fn check_migration(&mut self, snapshot: &ContainerSnapshot) -> MigrationReport {
+        let mut report = MigrationReport::default();
+
+        let instance = match self.guest.instance() {
+            Ok(instance) => instance,
+            Err(e) => {
+                report.mismatches.push(e.to_string());
+                return report;
+            }
+        };
+
+        snapshot.layout.check_migration(&self.layout, &mut report);
+        snapshot
+            .wasm
+            .check_migration(&mut self.store, &instance, &mut report);
+        report
+    }
+
+    /// Restore `snapshot`, which may be from a different build of the guest.
+    ///
+    /// This fails, listing every mismatch, unless [`Self::check_migration`]
+    /// finds the snapshot is compatible. On success, it returns the report,
+    /// which may contain warnings.
+    ///
+    /// Static data that differs from the snapshot's build is re-initialized
+    /// from this build.
+    pub fn migrate(&mut self, snapshot: &ContainerSnapshot) -> Result<MigrationReport> {
+        let report = self.check_migration(snapshot);
+        ensure!(
+            report.is_compatible(),
+            "Snapshot is incompatible with this module:\n{report}"
+        );
+        self.restore_ignoring_fingerprint(snapshot)?;
+        let instance = self.guest.instance()?;
+        self.static_data
+            .restore_changed(&snapshot.layout, &mut self.store, &instance)?;
+        Ok(report)
+    }
+
+    fn check_fingerprint(&self, fingerprint: ModuleFingerprint) -> Result<()> {
+        ensure!(
+            fingerprint == self.fingerprint,
+            "Snapshot was taken from a different module.\n\
+             Snapshot module: {fingerprint}\n\
+             Container module: {}",
+            self.fingerprint
+        );
+
+        Ok(())
     }
 
     pub fn register_workflows(&mut self) -> Result<()> {
-        let workflow_count = self.register_workflows.call(&mut self.store, ())?;
+        self.ensure_sync()?;
+        let workflow_count = self.limits.call(&mut self.store, |store| {
+            self.guest.register_workflows(store)
+        })?;
         println!("Registered {workflow_count} workflows");
         Ok(())
     }
 
     pub fn init_workflow(&mut self, module: &str, name: &str) -> Result<()> {
-        let index = *self
-            .workflow_indices
+        let index = self.workflow_index(module, name)?;
+        self.ensure_sync()?;
+        self.limits.call(&mut self.store, |store| {
+            self.guest.init_workflow(store, index)
+        })
+    }
+
+    /// Run the workflow until the next checkpoint.
+    ///
+    /// Traps and exceeded [`Limits`] are reported as a [`RunOutcome`]. Other
+    /// errors, such as the guest passing invalid strings to the host, fail
+    /// the call.
+    pub fn run(&mut self) -> Result<RunOutcome> {
+        self.ensure_sync()?;
+        let result = self
+            .limits
+            .call(&mut self.store, |store| self.guest.run(store));
+        self.outcome(result)
+    }
+
+    /// [`Self::register_workflows`], for containers with async support.
+    pub async fn register_workflows_async(&mut self) -> Result<()> {
+        self.ensure_async()?;
+        self.limits.prepare_async_call(&mut self.store)?;
+        let workflow_count = self
+            .limits
+            .enforce(self.guest.register_workflows_async(&mut self.store))
+            .await?;
+        println!("Registered {workflow_count} workflows");
+        Ok(())
+    }
+
+    /// [`Self::init_workflow`], for containers with async support.
+    pub async fn init_workflow_async(&mut self, module: &str, name: &str) -> Result<()> {
+        let index = self.workflow_index(module, name)?;
+        self.ensure_async()?;
+        self.limits.prepare_async_call(&mut self.store)?;
+        self.limits
+            .enforce(self.guest.init_workflow_async(&mut self.store, index))
+            .await
+    }
+
+    /// [`Self::run`], for containers with async support.
+    ///
+    /// The guest yields to the async executor every
+    /// [`EPOCH_TICK`][crate::limits::EPOCH_TICK].
+    pub async fn run_async(&mut self) -> Result<RunOutcome> {
+        self.ensure_async()?;
+        self.limits.prepare_async_call(&mut self.store)?;
+        let result = self
+            .limits
+            .enforce(self.guest.run_async(&mut self.store))
+            .await;
+        self.outcome(result)
+    }
+
+    /// The outcome of a call to the guest's `run`.
+    ///
+    /// If the guest failed, any running nodes in the call tree are marked as
+    /// failed.
+    fn outcome(&mut self, result: Result<bool>) -> Result<RunOutcome> {
+        let outcome = self.store.data_mut().take_report().outcome(result);
+        let error = match &outcome {
+            Ok(outcome) => outcome.error(),
+            Err(e) => Some(format!("{e:#}")),
+        };
+
+        if let Some(error) = error {
+            self.thread.write().unwrap().fail(&error);
+        }
+
+        outcome
+    }
+
+    pub fn node_store(&self) -> NodeStore {
+        self.thread.read().unwrap().node_store()
+    }
+
+    fn workflow_index(&self, module: &str, name: &str) -> Result<u32> {
+        self.workflow_indices
             .read()
             .unwrap()
             .get(&WorkflowKey::new(module, name))
-            .with_context(|| format!("Unknown workflow {module}::{name}"))?;
-        self.init_workflow.call(&mut self.store, index)?;
+            .copied()
+            .with_context(|| format!("Unknown workflow {module}::{name}"))
+    }
+
+    fn ensure_sync(&self) -> Result<()> {
+        ensure!(
+            !self.store.engine().is_async(),
+            "Container has async support, so use the async methods"
+        );
         Ok(())
     }
 
-    pub fn run(&mut self) -> Result<bool> {
-        Ok(self.run.call(&mut self.store, ())? != 0)
+    fn ensure_async(&self) -> Result<()> {
+        ensure!(
+            self.store.engine().is_async(),
+            "Container doesn't have async support"
+        );
+        Ok(())
     }
+}
 
-    pub fn node_store(&self) -> NodeStore {
-        self.thread.read().unwrap().node_store()
+/// The guest's exports.
+enum Guest {
+    Module(ModuleGuest),
+    Component(ComponentGuest),
+}
+
+impl Guest {
+    fn register_workflows(&self, store: &mut Store<StoreData>) -> Result<u32> {
+        match self {
+            Self::Module(guest) => guest.register_workflows.call(store, ()),
+            Self::Component(guest) => guest.register_workflows(store),
+        }
+    }
+
+    fn init_workflow(&self, store: &mut Store<StoreData>, index: u32) -> Result<()> {
+        match self {
+            Self::Module(guest) => guest.init_workflow.call(store, index),
+            Self::Component(guest) => guest.init_workflow(store, index),
+        }
+    }
+
+    fn run(&self, store: &mut Store<StoreData>) -> Result<bool> {
+        match self {
+            Self::Module(guest) => Ok(guest.run.call(store, ())? != 0),
+            Self::Component(guest) => guest.run(store),
+        }
+    }
+
+    async fn register_workflows_async(&self, store: &mut Store<StoreData>) -> Result<u32> {
+        self.module()?
+            .register_workflows
+            .call_async(store, ())
+            .await
+    }
+
+    async fn init_workflow_async(&self, store: &mut Store<StoreData>, index: u32) -> Result<()> {
+        self.module()?.init_workflow.call_async(store, index).await
+    }
+
+    async fn run_async(&self, store: &mut Store<StoreData>) -> Result<bool> {
+        Ok(self.module()?.run.call_async(store, ()).await? != 0)
+    }
+
+    /// The core module guest. Components don't support async.
+    fn module(&self) -> Result<&ModuleGuest> {
+        match self {
+            Self::Module(guest) => Ok(guest),
+            Self::Component(_) => bail!("Async support isn't available for components"),
+        }
+    }
+
+    /// The core module instance, which snapshots are taken from.
+    fn instance(&self) -> Result<Instance> {
+        match self {
+            Self::Module(guest) => Ok(guest.instance),
+            Self::Component(_) => {
+                bail!("Snapshots are only supported for core WASM modules, not components")
+            }
+        }
+    }
+}
+
+/// A core module guest, using the ABI in [`abi`].
+struct ModuleGuest {
+    instance: Instance,
+    register_workflows: TypedFunc<(), u32>,
+    init_workflow: TypedFunc<u32, ()>,
+    run: TypedFunc<(), i32>,
+}
+
+impl ModuleGuest {
+    fn new(store: &mut Store<StoreData>, instance: Instance) -> Result<Self> {
+        Ok(Self {
+            instance,
+            register_workflows: instance
+                .get_typed_func(&mut *store, "__codastraea_register_workflows")?,
+            init_workflow: instance.get_typed_func(&mut *store, "__codastraea_init_workflow")?,
+            run: instance.get_typed_func(&mut *store, "__codastraea_run")?,
+        })
+    }
+}
+
+/// A snapshot of the guest's WASM state, along with the host's call stack and
+/// call tree.
+pub struct ContainerSnapshot {
+    fingerprint: ModuleFingerprint,
+    layout: MemoryLayout,
+    wasm: Snapshot,
+    thread: ThreadSnapshot,
+    wasi: Option<WasiSnapshot>,
+}
+
+impl ContainerSnapshot {
+    pub fn fingerprint(&self) -> &ModuleFingerprint {
+        &self.fingerprint
+    }
+
+    /// The WASM state.
+    pub fn wasm(&self) -> &Snapshot {
+        &self.wasm
+    }
+
+    /// Write the snapshot to `writer`.
+    ///
+    /// See [`Snapshot::write_to`].
+    pub fn write_to(&self, mut writer: impl Write) -> Result<()> {
+        write_header(&mut writer, SnapshotKind::Container)?;
+        self.fingerprint.write(&mut writer)?;
+        self.layout.write(&mut writer)?;
+        self.wasm.write(&mut writer)?;
+        self.thread.write(&mut writer)?;
+        self.wasi.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a snapshot written with [`Self::write_to`].
+    pub fn read_from(mut reader: impl Read) -> Result<Self> {
+        read_header(&mut reader, SnapshotKind::Container)?;
+        Ok(Self {
+            fingerprint: ModuleFingerprint::read(&mut reader)?,
+            layout: MemoryLayout::read(&mut reader)?,
+            wasm: Snapshot::read(&mut reader)?,
+            thread: ThreadSnapshot::read(&mut reader)?,
+            wasi: Persist::read(&mut reader)?,
+        })
+    }
+}
+
+/// A chain of incremental [`ContainerSnapshot`]s.
+///
+/// See [`SnapshotChain`].
+pub struct ContainerSnapshotChain {
+    fingerprint: ModuleFingerprint,
+    layout: MemoryLayout,
+    wasm: SnapshotChain,
+    threads: Vec<ThreadSnapshot>,
+    wasi: Vec<Option<WasiSnapshot>>,
+}
+
+impl ContainerSnapshotChain {
+    pub fn fingerprint(&self) -> &ModuleFingerprint {
+        &self.fingerprint
+    }
+
+    /// The number of snapshots in the chain.
+    // A chain always contains its base snapshot, so it's never empty.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Rebuild the full snapshot at `index` in the chain.
+    pub fn get(&self, index: usize) -> Result<ContainerSnapshot> {
+        Ok(ContainerSnapshot {
+            fingerprint: self.fingerprint,
+            layout: self.layout.clone(),
+            wasm: self.wasm.get(index)?,
+            thread: self.threads[index].clone(),
+            wasi: self.wasi[index].clone(),
+        })
+    }
+
+    /// Write the chain to `writer`.
+    ///
+    /// See [`Snapshot::write_to`].
+    pub fn write_to(&self, mut writer: impl Write) -> Result<()> {
+        write_header(&mut writer, SnapshotKind::ContainerChain)?;
+        self.fingerprint.write(&mut writer)?;
+        self.layout.write(&mut writer)?;
+        self.wasm.write(&mut writer)?;
+        self.threads.write(&mut writer)?;
+        self.wasi.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a chain written with [`Self::write_to`].
+    pub fn read_from(mut reader: impl Read) -> Result<Self> {
+        read_header(&mut reader, SnapshotKind::ContainerChain)?;
+        let fingerprint = ModuleFingerprint::read(&mut reader)?;
+        let layout = MemoryLayout::read(&mut reader)?;
+        let wasm = SnapshotChain::read(&mut reader)?;
+        let threads = Vec::<ThreadSnapshot>::read(&mut reader)?;
+        let wasi = Vec::<Option<WasiSnapshot>>::read(&mut reader)?;
+        ensure!(
+            threads.len() == wasm.len() && wasi.len() == wasm.len(),
+            "Thread snapshots don't match the WASM snapshots"
+        );
+
+        Ok(Self {
+            fingerprint,
+            layout,
+            wasm,
+            threads,
+            wasi,
+        })
     }
 }
 
 fn define_register_workflow_index(
     workflow_indices: WorkflowIndices,
-    linker: &mut Linker<()>,
+    linker: &mut Linker<StoreData>,
     memory_export: ModuleExport,
 ) -> Result<()> {
     linker.func_wrap(
         LINKER_MODULE,
         "__codastraea_register_workflow_index",
-        move |mut caller: Caller<'_, ()>,
+        move |mut caller: Caller<'_, StoreData>,
               module_data: u32,
               module_len: u32,
               name_data: u32,
@@ -153,11 +1048,11 @@ fn define_register_workflow_index(
     Ok(())
 }
 
-fn define_log(linker: &mut Linker<()>, memory_export: ModuleExport) -> Result<()> {
+fn define_log(linker: &mut Linker<StoreData>, memory_export: ModuleExport) -> Result<()> {
     linker.func_wrap(
         LINKER_MODULE,
         "__codastraea_log",
-        move |mut caller: Caller<'_, ()>, data: u32, len: u32| {
+        move |mut caller: Caller<'_, StoreData>, data: u32, len: u32| {
             let message = read_string(memory(&mut caller, memory_export)?, data, len)?;
             println!("Log: {message}");
             Ok(())
@@ -166,18 +1061,36 @@ fn define_log(linker: &mut Linker<()>, memory_export: ModuleExport) -> Result<()
     Ok(())
 }
 
+fn define_report(
+    name: &'static str,
+    f: impl Fn(&mut GuestReport, String) + Send + Sync + 'static,
+    linker: &mut Linker<StoreData>,
+    memory_export: ModuleExport,
+) -> Result<()> {
+    linker.func_wrap(
+        LINKER_MODULE,
+        &format!("__codastraea_{name}"),
+        move |mut caller: Caller<'_, StoreData>, data: u32, len: u32| {
+            let value = read_string(memory(&mut caller, memory_export)?, data, len)?.to_string();
+            f(&mut caller.data_mut().report, value);
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
 fn define_trace_fn(
     fn_name: &'static str,
-    f: impl Fn(&mut Thread, &NodeType) + Send + Sync + 'static,
+    f: impl Fn(&mut Thread, &NodeType) -> Result<()> + Send + Sync + 'static,
     thread: &Arc<RwLock<Thread>>,
-    linker: &mut Linker<()>,
+    linker: &mut Linker<StoreData>,
     memory_export: ModuleExport,
 ) -> Result<()> {
     clone!(thread);
     linker.func_wrap(
         LINKER_MODULE,
         &format!("__codastraea_fn_{fn_name}"),
-        move |mut caller: Caller<()>,
+        move |mut caller: Caller<StoreData>,
               module_data: u32,
               module_len: u32,
               name_data: u32,
@@ -191,8 +1104,7 @@ fn define_trace_fn(
                 &NodeType::Call {
                     name: name.to_string(),
                 },
-            );
-            Ok(())
+            )
         },
     )?;
 
@@ -201,9 +1113,9 @@ fn define_trace_fn(
 
 fn define_trace(
     event: &'static str,
-    f: impl Fn(&mut Thread, &NodeType) + Send + Sync + 'static,
+    f: impl Fn(&mut Thread, &NodeType) -> Result<()> + Send + Sync + 'static,
     thread: &Arc<RwLock<Thread>>,
-    linker: &mut Linker<()>,
+    linker: &mut Linker<StoreData>,
     node_type: &NodeType,
 ) -> Result<()> {
     let snake_name = node_type.as_snake_str();
@@ -212,13 +1124,13 @@ fn define_trace(
 
     linker.func_wrap(LINKER_MODULE, &ident, move || {
         println!("{event} {}", node_type.as_snake_str());
-        f(&mut thread.write().unwrap(), &node_type);
+        f(&mut thread.write().unwrap(), &node_type)
     })?;
 
     Ok(())
 }
 
-fn memory<'a>(caller: &'a mut Caller<()>, memory_export: ModuleExport) -> Result<&'a [u8]> {
+fn memory<'a>(caller: &'a mut Caller<StoreData>, memory_export: ModuleExport) -> Result<&'a [u8]> {
     let Some(Extern::Memory(memory)) = caller.get_module_export(&memory_export) else {
         bail!("failed to find host memory")
     };
@@ -236,5 +1148,3 @@ fn read_string(memory: &[u8], data: u32, len: u32) -> Result<&str> {
     let string = str::from_utf8(data).context("Invalid utf-8")?;
     Ok(string)
 }
-
-const LINKER_MODULE: &str = "env";
@@ -2,32 +2,103 @@ use std::{
     collections::HashMap,
     ffi::c_void,
     io::{Read, Write},
+    ops::Range,
+    sync::atomic::{AtomicU8, Ordering},
 };
 
-use anyhow::{bail, Context, Result};
-use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
-use wasmtime::{AsContextMut, Func, Global, Instance, Memory, Ref, Table, Val};
+use anyhow::{bail, ensure, Context, Result};
+pub use codec::Codec;
+pub use persist::SnapshotKind;
+use persist::{read_bytes, read_header, write_bytes, write_header, Persist};
+use verify::Digests;
+use wasmtime::{AsContextMut, Func, Global, Instance, Ref, SharedMemory, Table, Val};
 
-// # TODO
-//
-// ## ExternRef/AnyRef
-//
-// Need to investigate these.
-//
-// ## Shared Memory
+use crate::handle::{HandleId, HandleRegistry, HostHandle, RestoredHandles};
+
+pub mod chain;
+mod codec;
+pub mod inspect;
+pub mod migrate;
+pub(crate) mod persist;
+mod verify;
+
+// # `AnyRef`s
 //
-// - Memories in Walrus just have a shared flag to identify them, so they should
-//   already be exported by the instrumentation.
-// - Should we be copying shared memory or rejecting anything with shared
-//   memory? All threads should at least be stopped before a snapshot.
+// Only null `AnyRef`s are supported. Non-null `AnyRef`s are i31refs or GC
+// objects, which need the GC proposal, and engines created by the host don't
+// enable it. Host resources should be passed to the guest as `ExternRef`s,
+// which are supported via `HostHandle`s.
 
+#[derive(Clone)]
 pub struct Snapshot {
     globals: Globals,
     memories: NamedVec<SnapshotMemory>,
     tables: NamedVec<Vec<TableEntry>>,
+    digests: Option<Digests>,
+}
+
+/// Read the header of a persisted snapshot, to find out what kind it is.
+///
+/// Then read the snapshot from the start, with the `read_from` method for its
+/// kind.
+pub fn read_kind(mut reader: impl Read) -> Result<SnapshotKind> {
+    persist::read_kind(&mut reader)
+}
+
+/// Options for taking a [`Snapshot`].
+#[derive(Clone, Default)]
+pub struct SnapshotOptions {
+    /// Record digests of each memory, table and global, and check them after
+    /// a restore.
+    ///
+    /// This is useful for checking restores reproduce the snapshotted state
+    /// exactly, but it makes snapshots and restores slower.
+    pub verify: bool,
+    /// How to compress memory.
+    pub codec: Codec,
+    /// What to do with shared memories.
+    pub shared_memory: SharedMemoryPolicy,
+}
+
+/// How snapshots handle shared memories.
+///
+/// Shared memories can be modified by other threads while we're copying them,
+/// and the host has no way to stop those threads, so by default we reject
+/// them. Snapshots containing shared memories can't be restored, as threads in
+/// the target instance could be using the memory.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum SharedMemoryPolicy {
+    /// Fail to snapshot any guest with a shared memory.
+    #[default]
+    Reject,
+    /// Copy shared memories, so the snapshot can be inspected or diffed.
+    ///
+    /// If other threads are writing to the memory, the copy may not be
+    /// consistent. The host doesn't provide a way for guests to spawn threads,
+    /// so this only happens if the memory is shared with other instances that
+    /// are running.
+    Copy,
+}
+
+impl Persist for SharedMemoryPolicy {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        match self {
+            Self::Reject => 0u8,
+            Self::Copy => 1u8,
+        }
+        .write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(match u8::read(r)? {
+            0 => Self::Reject,
+            1 => Self::Copy,
+            tag => bail!("Invalid shared memory policy tag {tag}"),
+        })
+    }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 struct Globals {
     i32s: NamedVec<i32>,
     i64s: NamedVec<i64>,
@@ -35,6 +106,7 @@ struct Globals {
     f64s: NamedVec<f64>,
     v128s: NamedVec<u128>,
     functions: NamedVec<Option<String>>,
+    extern_refs: NamedVec<HandleId>,
     null_extern_ref_names: Vec<String>,
     null_any_ref_names: Vec<String>,
 }
@@ -59,13 +131,15 @@ impl Globals {
                 Val::FuncRef(func) => self
                     .functions
                     .push((name, lookup_func_name.get(&mut *ctx, &func)?)),
+                Val::ExternRef(Some(extern_ref)) => {
+                    let handle = HostHandle::from_extern_ref(&mut *ctx, &extern_ref)
+                        .with_context(|| format!("Global '{name}'"))?;
+                    self.extern_refs.push((name, handle.id().clone()))
+                }
                 Val::ExternRef(None) => self.null_extern_ref_names.push(name),
                 Val::AnyRef(None) => self.null_any_ref_names.push(name),
-                Val::ExternRef(_) => {
-                    bail!("Global '{name}': Mutable `ExternRef`s are not supported")
-                }
                 Val::AnyRef(_) => {
-                    bail!("Global '{name}': Mutable `AnyRef`s are not supported")
+                    bail!("Global '{name}': Non-null `AnyRef`s are not supported")
                 }
             }
         }
@@ -73,7 +147,12 @@ impl Globals {
         Ok(())
     }
 
-    fn restore(&self, ctx: &mut impl AsContextMut, instance: &Instance) -> Result<()> {
+    fn restore(
+        &self,
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+        handles: &mut RestoredHandles,
+    ) -> Result<()> {
         Self::set_globals(ctx, instance, &self.i32s)?;
         Self::set_globals(ctx, instance, &self.i64s)?;
         Self::set_globals(ctx, instance, &self.f32s)?;
@@ -85,6 +164,11 @@ impl Globals {
             Self::set_global(ctx, instance, name, Val::FuncRef(func))?;
         }
 
+        for (name, handle_id) in &self.extern_refs {
+            let extern_ref = handles.extern_ref(&mut *ctx, handle_id)?;
+            Self::set_global(ctx, instance, name, Val::ExternRef(Some(extern_ref)))?;
+        }
+
         for name in &self.null_extern_ref_names {
             Self::set_global(ctx, instance, name, Val::ExternRef(None))?;
         }
@@ -122,97 +206,294 @@ impl Globals {
     }
 }
 
+impl Persist for Globals {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.i32s.write(w)?;
+        self.i64s.write(w)?;
+        self.f32s.write(w)?;
+        self.f64s.write(w)?;
+        self.v128s.write(w)?;
+        self.functions.write(w)?;
+        self.extern_refs.write(w)?;
+        self.null_extern_ref_names.write(w)?;
+        self.null_any_ref_names.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            i32s: Persist::read(r)?,
+            i64s: Persist::read(r)?,
+            f32s: Persist::read(r)?,
+            f64s: Persist::read(r)?,
+            v128s: Persist::read(r)?,
+            functions: Persist::read(r)?,
+            extern_refs: Persist::read(r)?,
+            null_extern_ref_names: Persist::read(r)?,
+            null_any_ref_names: Persist::read(r)?,
+        })
+    }
+}
+
 type NamedVec<T> = Vec<(String, T)>;
 
+#[derive(Clone)]
 struct SnapshotMemory {
     page_size: u64,
     uncompressed_len: usize,
+    /// Byte ranges that are all zero. These aren't stored in `data`.
+    zero_ranges: Vec<Range<usize>>,
+    codec: Codec,
+    /// The compressed contents of everything outside `zero_ranges`.
     data: Vec<u8>,
 }
 
-impl Snapshot {
-    pub fn new(ctx: &mut impl AsContextMut, instance: &Instance) -> Result<Self> {
-        let mut globals = Globals::default();
-        let mut memories = Vec::new();
-        let mut tables = Vec::new();
-
-        let exported_names: Vec<String> = instance
-            .exports(&mut *ctx)
-            .map(|e| e.name().to_string())
-            .collect();
+impl SnapshotMemory {
+    fn new(page_size: u64, memory: &[u8], codec: Codec) -> Result<Self> {
+        let mut zero_ranges: Vec<Range<usize>> = Vec::new();
+        let mut non_zero = Vec::new();
 
-        let lookup_func_name = FunctionNames::new(ctx, instance, &exported_names);
+        for (index, block) in memory.chunks(ZERO_BLOCK_SIZE).enumerate() {
+            let start = index * ZERO_BLOCK_SIZE;
+            let end = start + block.len();
 
-        for name in exported_names {
-            if let Some(global) = instance.get_global(&mut *ctx, &name) {
-                globals.snapshot(ctx, &lookup_func_name, &name, global)?;
+            if block.iter().all(|&byte| byte == 0) {
+                match zero_ranges.last_mut() {
+                    Some(last) if last.end == start => last.end = end,
+                    _ => zero_ranges.push(start..end),
+                }
+            } else {
+                non_zero.extend_from_slice(block);
             }
+        }
 
-            if let Some(memory) = instance.get_memory(&mut *ctx, &name) {
-                memories.push((
-                    name.clone(),
-                    snapshot_memory(ctx, memory).with_context(|| format!("Memory {name}"))?,
-                ));
-            }
+        Ok(Self {
+            page_size,
+            uncompressed_len: memory.len(),
+            zero_ranges,
+            codec,
+            data: codec.compress(&non_zero)?,
+        })
+    }
 
-            if let Some(table) = instance.get_table(&mut *ctx, &name) {
-                tables.push((
-                    name.clone(),
-                    snapshot_table(ctx, &lookup_func_name, table)
-                        .with_context(|| format!("Table {name}"))?,
-                ));
-            }
+    /// Decompress into `output`, which must be `uncompressed_len` bytes.
+    ///
+    /// Zero ranges are only written if they're not already zero in `output`,
+    /// so we don't touch pages that were never written.
+    fn decompress_into(&self, output: &mut [u8]) -> Result<()> {
+        ensure!(
+            output.len() == self.uncompressed_len,
+            "Unexpected data length"
+        );
+        let mut non_zero_ranges = Vec::new();
+        let mut start = 0;
+
+        for zero_range in &self.zero_ranges {
+            ensure!(
+                start <= zero_range.start
+                    && zero_range.start <= zero_range.end
+                    && zero_range.end <= output.len(),
+                "Invalid zero range"
+            );
+            non_zero_ranges.push(start..zero_range.start);
+            zero_if_dirty(&mut output[zero_range.clone()]);
+            start = zero_range.end;
+        }
+
+        non_zero_ranges.push(start..output.len());
+
+        let mut non_zero = vec![0; non_zero_ranges.iter().map(|range| range.len()).sum()];
+        self.codec.decompress_into(&self.data, &mut non_zero)?;
+        let mut non_zero = non_zero.as_slice();
+
+        for range in non_zero_ranges {
+            let (data, rest) = non_zero.split_at(range.len());
+            output[range].copy_from_slice(data);
+            non_zero = rest;
+        }
+
+        Ok(())
+    }
+
+    fn required_pages(&self, name: &str, page_size: u64) -> Result<u64> {
+        if page_size != self.page_size {
+            bail!(
+                "Page size of instance memory {name} is {page_size} and doesn't match {}",
+                self.page_size
+            );
         }
 
+        let snapshot_bytes: u64 = self.uncompressed_len.try_into()?;
+        let required_pages = snapshot_bytes.div_ceil(page_size);
+        assert!(required_pages * page_size >= snapshot_bytes);
+
+        Ok(required_pages)
+    }
+
+    /// Restore into `memory`, which must be at least `uncompressed_len` bytes.
+    fn restore_into(&self, memory: &mut [u8]) -> Result<()> {
+        let (data, excess) = memory.split_at_mut(self.uncompressed_len);
+        self.decompress_into(data)?;
+        // WASM memories can't shrink, so zero anything past the end of the
+        // snapshot.
+        zero_if_dirty(excess);
+
+        Ok(())
+    }
+}
+
+/// The granularity we detect zeroed memory at.
+const ZERO_BLOCK_SIZE: usize = 4096;
+
+fn zero_if_dirty(data: &mut [u8]) {
+    if data.iter().any(|&byte| byte != 0) {
+        data.fill(0);
+    }
+}
+
+impl Persist for SnapshotMemory {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.page_size.write(w)?;
+        self.uncompressed_len.write(w)?;
+        self.zero_ranges.write(w)?;
+        self.codec.write(w)?;
+        write_bytes(w, &self.data)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            page_size: Persist::read(r)?,
+            uncompressed_len: Persist::read(r)?,
+            zero_ranges: Persist::read(r)?,
+            codec: Persist::read(r)?,
+            data: read_bytes(r)?,
+        })
+    }
+}
+
+impl Persist for SnapshotOptions {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.verify.write(w)?;
+        self.codec.write(w)?;
+        self.shared_memory.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            verify: Persist::read(r)?,
+            codec: Persist::read(r)?,
+            shared_memory: Persist::read(r)?,
+        })
+    }
+}
+
+impl Snapshot {
+    pub fn new(ctx: &mut impl AsContextMut, instance: &Instance) -> Result<Self> {
+        Self::with_options(ctx, instance, &SnapshotOptions::default())
+    }
+
+    pub fn with_options(
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+        options: &SnapshotOptions,
+    ) -> Result<Self> {
+        let (globals, memories, tables) = capture(
+            ctx,
+            instance,
+            options.shared_memory,
+            |_name, page_size, data| SnapshotMemory::new(page_size, data, options.codec),
+        )?;
+
         Ok(Self {
             globals,
             memories,
             tables,
+            digests: Digests::optional(ctx, instance, options)?,
         })
     }
 
+    /// Write the snapshot to `writer`.
+    ///
+    /// The format is binary, with a header and version number. Use
+    /// [`Self::read_from`] to read it back.
+    pub fn write_to(&self, mut writer: impl Write) -> Result<()> {
+        write_header(&mut writer, SnapshotKind::Wasm)?;
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a snapshot written with [`Self::write_to`].
+    pub fn read_from(mut reader: impl Read) -> Result<Self> {
+        read_header(&mut reader, SnapshotKind::Wasm)?;
+        Self::read(&mut reader)
+    }
+
+    /// Restore the snapshot into `instance`.
+    ///
+    /// If the snapshot was taken with [`SnapshotOptions::verify`], this checks
+    /// the restored state matches the snapshot, and reports any items that
+    /// diverged.
+    ///
+    /// This fails if any of the memories are shared. See
+    /// [`SharedMemoryPolicy`].
     pub fn restore(&self, ctx: &mut impl AsContextMut, instance: &Instance) -> Result<()> {
-        self.globals.restore(ctx, instance)?;
-        self.restore_tables(ctx, instance)?;
+        self.restore_with_handles(ctx, instance, &HandleRegistry::default())
+    }
+
+    /// Restore the snapshot, recreating any host handles with `handles`.
+    ///
+    /// See [`Self::restore`].
+    pub fn restore_with_handles(
+        &self,
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+        handles: &HandleRegistry,
+    ) -> Result<()> {
+        for (name, _) in &self.memories {
+            ensure!(
+                instance.get_shared_memory(&mut *ctx, name).is_none(),
+                "Memory '{name}' is shared, and restoring shared memories isn't supported, as \
+                 other threads could be using them"
+            );
+        }
+
+        let mut handles = RestoredHandles::new(handles);
+        self.globals.restore(ctx, instance, &mut handles)?;
+        self.restore_tables(ctx, instance, &mut handles)?;
         self.restore_memories(ctx, instance)?;
 
+        if let Some(digests) = &self.digests {
+            digests.verify(ctx, instance)?;
+        }
+
         Ok(())
     }
 
     fn restore_memories(&self, ctx: &mut impl AsContextMut, instance: &Instance) -> Result<()> {
         for (name, snapshot) in &self.memories {
-            let memory = instance
-                .get_memory(&mut *ctx, name)
-                .with_context(|| format!("Couldn't find memory '{name}"))?;
-            let page_size = memory.page_size(&mut *ctx);
-
-            if page_size != snapshot.page_size {
-                bail!(
-                    "Page size of instance memory {name} is {page_size} and doesn't match {}",
-                    snapshot.page_size
-                );
-            }
-
-            let snapshot_bytes: u64 = snapshot.uncompressed_len.try_into()?;
-            let required_pages = snapshot_bytes.div_ceil(page_size);
-            assert!(required_pages * page_size >= snapshot_bytes);
+            if let Some(memory) = instance.get_memory(&mut *ctx, name) {
+                let required_pages = snapshot.required_pages(name, memory.page_size(&mut *ctx))?;
+                let current_pages = memory.size(&mut *ctx);
 
-            let current_pages = memory.size(&mut *ctx);
+                if current_pages < required_pages {
+                    memory.grow(&mut *ctx, required_pages - current_pages)?;
+                }
 
-            if current_pages < required_pages {
-                memory.grow(&mut *ctx, required_pages - current_pages)?;
+                snapshot.restore_into(memory.data_mut(&mut *ctx))?;
+            } else {
+                bail!("Couldn't find memory '{name}'");
             }
-
-            let mut decoder = DeflateDecoder::new(&snapshot.data[..]);
-            decoder.read_exact(&mut memory.data_mut(&mut *ctx)[..snapshot.uncompressed_len])?;
-
-            memory.write(&mut *ctx, 0, &snapshot.data)?;
         }
 
         Ok(())
     }
 
-    fn restore_tables(&self, ctx: &mut impl AsContextMut, instance: &Instance) -> Result<()> {
+    fn restore_tables(
+        &self,
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+        handles: &mut RestoredHandles,
+    ) -> Result<()> {
         for (name, snapshot_table) in &self.tables {
             let table = instance
                 .get_table(&mut *ctx, name)
@@ -224,7 +505,10 @@ impl Snapshot {
             let table_size = table.size(&*ctx);
 
             if let Some(delta) = snapshot_len.checked_sub(table_size) {
-                table.grow(&mut *ctx, delta, Ref::Func(None))?;
+                if delta > 0 {
+                    let null = Ref::null(table.ty(&*ctx).element().heap_type());
+                    table.grow(&mut *ctx, delta, null)?;
+                }
             }
 
             for (index, table_entry) in snapshot_table.iter().enumerate() {
@@ -232,23 +516,91 @@ impl Snapshot {
                 use TableEntry as TE;
                 let item = match table_entry {
                     TE::Func(func_name) => Ref::Func(get_function(ctx, instance, func_name)?),
+                    TE::ExternRef(handle_id) => {
+                        Ref::Extern(Some(handles.extern_ref(&mut *ctx, handle_id)?))
+                    }
                     TE::NullExternRef => Ref::Extern(None),
                     TE::NullAnyRef => Ref::Any(None),
                 };
                 table.set(&mut *ctx, index, item)?;
             }
+
+            // Tables can't shrink, so clear anything past the end of the
+            // snapshot.
+            if let Some(excess) = table_size.checked_sub(snapshot_len) {
+                if excess > 0 {
+                    let null = Ref::null(table.ty(&*ctx).element().heap_type());
+                    table.fill(&mut *ctx, snapshot_len, null, excess)?;
+                }
+            }
         }
 
         Ok(())
     }
 }
 
+impl Persist for Snapshot {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.globals.write(w)?;
+        self.memories.write(w)?;
+        self.tables.write(w)?;
+        self.digests.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            globals: Persist::read(r)?,
+            memories: Persist::read(r)?,
+            tables: Persist::read(r)?,
+            digests: Persist::read(r)?,
+        })
+    }
+}
+
+#[derive(Clone)]
 enum TableEntry {
     Func(Option<String>),
+    ExternRef(HandleId),
     NullExternRef,
     NullAnyRef,
 }
 
+impl TableEntry {
+    fn is_null(&self) -> bool {
+        matches!(
+            self,
+            Self::Func(None) | Self::NullExternRef | Self::NullAnyRef
+        )
+    }
+}
+
+impl Persist for TableEntry {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        match self {
+            Self::Func(func_name) => {
+                0u8.write(w)?;
+                func_name.write(w)
+            }
+            Self::NullExternRef => 1u8.write(w),
+            Self::NullAnyRef => 2u8.write(w),
+            Self::ExternRef(handle_id) => {
+                3u8.write(w)?;
+                handle_id.write(w)
+            }
+        }
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(match u8::read(r)? {
+            0 => Self::Func(Persist::read(r)?),
+            1 => Self::NullExternRef,
+            2 => Self::NullAnyRef,
+            3 => Self::ExternRef(Persist::read(r)?),
+            tag => bail!("Invalid table entry tag {tag}"),
+        })
+    }
+}
+
 fn snapshot_table(
     ctx: &mut impl AsContextMut,
     lookup_func_name: &FunctionNames,
@@ -262,10 +614,15 @@ fn snapshot_table(
 
         let item = match item {
             Ref::Func(func) => TableEntry::Func(lookup_func_name.get(&mut *ctx, &func)?),
+            Ref::Extern(Some(extern_ref)) => TableEntry::ExternRef(
+                HostHandle::from_extern_ref(&mut *ctx, &extern_ref)
+                    .with_context(|| format!("Table index {index}"))?
+                    .id()
+                    .clone(),
+            ),
             Ref::Extern(None) => TableEntry::NullExternRef,
             Ref::Any(None) => TableEntry::NullAnyRef,
-            Ref::Extern(_) => bail!("`ExternRef`s are not supported"),
-            Ref::Any(_) => bail!("`AnyRef`s are not supported"),
+            Ref::Any(_) => bail!("Table index {index}: Non-null `AnyRef`s are not supported"),
         };
 
         table_data.push(item);
@@ -274,16 +631,80 @@ fn snapshot_table(
     Ok(table_data)
 }
 
-fn snapshot_memory(ctx: &mut impl AsContextMut, memory: Memory) -> Result<SnapshotMemory> {
-    let mut compressor = DeflateEncoder::new(Vec::new(), Compression::default());
-    compressor.write_all(memory.data(&mut *ctx))?;
-    let uncompressed_len = memory.data_size(&mut *ctx);
-    let data = compressor.finish()?;
-    Ok(SnapshotMemory {
-        page_size: memory.page_size(&mut *ctx),
-        uncompressed_len,
-        data,
-    })
+/// Capture the globals, memories and tables exported from `instance`.
+///
+/// `snapshot_memory` decides how each memory is captured.
+fn capture<M>(
+    ctx: &mut impl AsContextMut,
+    instance: &Instance,
+    shared_memory: SharedMemoryPolicy,
+    mut snapshot_memory: impl FnMut(&str, u64, &[u8]) -> Result<M>,
+) -> Result<(Globals, NamedVec<M>, NamedVec<Vec<TableEntry>>)> {
+    let mut globals = Globals::default();
+    let mut memories = Vec::new();
+    let mut tables = Vec::new();
+
+    let exported_names: Vec<String> = instance
+        .exports(&mut *ctx)
+        .map(|e| e.name().to_string())
+        .collect();
+
+    let lookup_func_name = FunctionNames::new(ctx, instance, &exported_names);
+
+    for name in exported_names {
+        if let Some(global) = instance.get_global(&mut *ctx, &name) {
+            globals.snapshot(ctx, &lookup_func_name, &name, global)?;
+        }
+
+        if let Some(memory) = instance.get_memory(&mut *ctx, &name) {
+            let page_size = memory.page_size(&mut *ctx);
+            memories.push((
+                name.clone(),
+                snapshot_memory(&name, page_size, memory.data(&mut *ctx))
+                    .with_context(|| format!("Memory {name}"))?,
+            ));
+        }
+
+        if let Some(memory) = instance.get_shared_memory(&mut *ctx, &name) {
+            if shared_memory == SharedMemoryPolicy::Reject {
+                bail!(
+                    "Memory '{name}' is shared, and shared memories are rejected by the \
+                     snapshot options. Use `SharedMemoryPolicy::Copy` to take a snapshot that \
+                     can be inspected, but not restored."
+                );
+            }
+
+            memories.push((
+                name.clone(),
+                snapshot_memory(&name, memory.page_size().into(), &copy_shared(&memory))
+                    .with_context(|| format!("Memory {name}"))?,
+            ));
+        }
+
+        if let Some(table) = instance.get_table(&mut *ctx, &name) {
+            tables.push((
+                name.clone(),
+                snapshot_table(ctx, &lookup_func_name, table)
+                    .with_context(|| format!("Table {name}"))?,
+            ));
+        }
+    }
+
+    Ok((globals, memories, tables))
+}
+
+/// Copy `memory`, which other threads may be accessing.
+fn copy_shared(memory: &SharedMemory) -> Vec<u8> {
+    memory
+        .data()
+        .iter()
+        .map(|byte| {
+            // Safety: `byte` is valid for as long as `memory`, and is only
+            // accessed atomically here, so concurrent accesses from other
+            // threads can't cause a data race.
+            unsafe { AtomicU8::from_ptr(byte.get()) }.load(Ordering::Relaxed)
+        })
+        .collect()
 }
 
 struct FunctionNames(HashMap<*mut c_void, String>);
@@ -0,0 +1,325 @@
+//! Incremental snapshots.
+//!
+//! A [`SnapshotChain`] starts with a full [`Snapshot`], and each subsequent
+//! snapshot only stores the memory pages that have changed since the previous
+//! one. Globals and tables are small, so they're always stored in full.
+use std::io::{Read, Write};
+
+use anyhow::{ensure, Context, Result};
+use wasmtime::{AsContextMut, Instance};
+
+use super::{
+    capture,
+    persist::{read_bytes, read_header, write_bytes, write_header, Persist, SnapshotKind},
+    verify::Digests,
+    Codec, Globals, NamedVec, Snapshot, SnapshotMemory, SnapshotOptions, TableEntry,
+};
+
+pub struct SnapshotChain {
+    options: SnapshotOptions,
+    base: Snapshot,
+    deltas: Vec<DeltaSnapshot>,
+    /// The uncompressed memories at the end of the chain, so we can find
+    /// changed pages without decompressing the whole chain.
+    head_memories: NamedVec<Vec<u8>>,
+}
+
+impl SnapshotChain {
+    /// Start a new chain with a full snapshot.
+    pub fn new(ctx: &mut impl AsContextMut, instance: &Instance) -> Result<Self> {
+        Self::with_options(ctx, instance, SnapshotOptions::default())
+    }
+
+    /// Start a new chain, using `options` for every snapshot in the chain.
+    pub fn with_options(
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+        options: SnapshotOptions,
+    ) -> Result<Self> {
+        let base = Snapshot::with_options(ctx, instance, &options)?;
+        let head_memories = decompress_memories(&base)?;
+
+        Ok(Self {
+            options,
+            base,
+            deltas: Vec::new(),
+            head_memories,
+        })
+    }
+
+    /// Add a snapshot to the end of the chain.
+    ///
+    /// Only memory pages that differ from the previous snapshot in the chain
+    /// are stored.
+    ///
+    /// If this fails, the chain is left unchanged.
+    pub fn push(&mut self, ctx: &mut impl AsContextMut, instance: &Instance) -> Result<()> {
+        let head_memories = &self.head_memories;
+        let codec = self.options.codec;
+        let (globals, memories, tables) = capture(
+            ctx,
+            instance,
+            self.options.shared_memory,
+            |name, page_size, data| {
+                let head = head_memories
+                    .iter()
+                    .find_map(|(head_name, head)| (head_name == name).then_some(head))
+                    .context("Memory not found in parent snapshot")?;
+                Ok((
+                    MemoryDelta::new(page_size, data, head, codec)?,
+                    data.to_vec(),
+                ))
+            },
+        )?;
+
+        ensure!(
+            memories.len() == self.head_memories.len(),
+            "Memories don't match the parent snapshot"
+        );
+
+        let digests = Digests::optional(ctx, instance, &self.options)?;
+        let (memories, head_memories) = memories
+            .into_iter()
+            .map(|(name, (delta, data))| ((name.clone(), delta), (name, data)))
+            .unzip();
+        self.head_memories = head_memories;
+        self.deltas.push(DeltaSnapshot {
+            globals,
+            memories,
+            tables,
+            digests,
+        });
+
+        Ok(())
+    }
+
+    /// The number of snapshots in the chain, including the base snapshot.
+    // A chain always contains its base snapshot, so it's never empty.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.deltas.len() + 1
+    }
+
+    /// Rebuild the full snapshot at `index` in the chain.
+    ///
+    /// Index `0` is the base snapshot.
+    pub fn get(&self, index: usize) -> Result<Snapshot> {
+        ensure!(
+            index < self.len(),
+            "Snapshot {index} is out of range for a chain of length {}",
+            self.len()
+        );
+
+        let Some(last_delta) = index.checked_sub(1) else {
+            return Ok(self.base.clone());
+        };
+
+        let mut memories = decompress_memories(&self.base)?;
+        let deltas = &self.deltas[..=last_delta];
+
+        for delta in deltas {
+            delta.apply(&mut memories)?;
+        }
+
+        let delta = &deltas[last_delta];
+        let memories = memories
+            .into_iter()
+            .zip(&delta.memories)
+            .map(|((name, data), (_, memory))| {
+                Ok((
+                    name,
+                    SnapshotMemory::new(memory.page_size, &data, self.options.codec)?,
+                ))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Snapshot {
+            globals: delta.globals.clone(),
+            memories,
+            tables: delta.tables.clone(),
+            digests: delta.digests.clone(),
+        })
+    }
+
+    /// Write the chain to `writer`.
+    ///
+    /// See [`Snapshot::write_to`].
+    pub fn write_to(&self, mut writer: impl Write) -> Result<()> {
+        write_header(&mut writer, SnapshotKind::Chain)?;
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a chain written with [`Self::write_to`].
+    pub fn read_from(mut reader: impl Read) -> Result<Self> {
+        read_header(&mut reader, SnapshotKind::Chain)?;
+        Self::read(&mut reader)
+    }
+}
+
+impl Persist for SnapshotChain {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.options.write(w)?;
+        self.base.write(w)?;
+        self.deltas.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        let options = SnapshotOptions::read(r)?;
+        let base = Snapshot::read(r)?;
+        let deltas = Vec::<DeltaSnapshot>::read(r)?;
+        let mut head_memories = decompress_memories(&base)?;
+
+        for delta in &deltas {
+            delta.apply(&mut head_memories)?;
+        }
+
+        Ok(Self {
+            options,
+            base,
+            deltas,
+            head_memories,
+        })
+    }
+}
+
+struct DeltaSnapshot {
+    globals: Globals,
+    memories: NamedVec<MemoryDelta>,
+    tables: NamedVec<Vec<TableEntry>>,
+    digests: Option<Digests>,
+}
+
+impl DeltaSnapshot {
+    fn apply(&self, memories: &mut NamedVec<Vec<u8>>) -> Result<()> {
+        ensure!(
+            memories.len() == self.memories.len(),
+            "Memories don't match the parent snapshot"
+        );
+
+        for ((name, data), (delta_name, delta)) in memories.iter_mut().zip(&self.memories) {
+            ensure!(
+                name == delta_name,
+                "Expected memory '{name}', found '{delta_name}'"
+            );
+            delta
+                .apply(data)
+                .with_context(|| format!("Memory {name}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Persist for DeltaSnapshot {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.globals.write(w)?;
+        self.memories.write(w)?;
+        self.tables.write(w)?;
+        self.digests.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            globals: Persist::read(r)?,
+            memories: Persist::read(r)?,
+            tables: Persist::read(r)?,
+            digests: Persist::read(r)?,
+        })
+    }
+}
+
+struct MemoryDelta {
+    page_size: u64,
+    uncompressed_len: usize,
+    codec: Codec,
+    /// The indices of pages that differ from the parent snapshot.
+    changed_pages: Vec<u64>,
+    /// The changed pages, concatenated and compressed.
+    data: Vec<u8>,
+}
+
+impl MemoryDelta {
+    /// Find the pages in `data` that differ from `head`.
+    fn new(page_size: u64, data: &[u8], head: &[u8], codec: Codec) -> Result<Self> {
+        let page_size_bytes = page_size.try_into()?;
+        let mut changed_pages = Vec::new();
+        let mut changed_data = Vec::new();
+
+        for (index, page) in data.chunks(page_size_bytes).enumerate() {
+            let start = index * page_size_bytes;
+            let head_page = head.get(start..start + page.len());
+
+            if head_page != Some(page) {
+                changed_pages.push(index.try_into()?);
+                changed_data.extend_from_slice(page);
+            }
+        }
+
+        Ok(Self {
+            page_size,
+            uncompressed_len: data.len(),
+            codec,
+            changed_pages,
+            data: codec.compress(&changed_data)?,
+        })
+    }
+
+    fn apply(&self, memory: &mut Vec<u8>) -> Result<()> {
+        let page_size: usize = self.page_size.try_into()?;
+        memory.resize(self.uncompressed_len, 0);
+        let mut page_ranges = Vec::new();
+
+        for &page_index in &self.changed_pages {
+            let start = usize::try_from(page_index)? * page_size;
+            let end = (start + page_size).min(memory.len());
+            ensure!(start < end, "Page index out of bounds");
+            page_ranges.push(start..end);
+        }
+
+        let mut changed_data = vec![0; page_ranges.iter().map(|range| range.len()).sum()];
+        self.codec.decompress_into(&self.data, &mut changed_data)?;
+        let mut changed_data = changed_data.as_slice();
+
+        for range in page_ranges {
+            let (page, rest) = changed_data.split_at(range.len());
+            memory[range].copy_from_slice(page);
+            changed_data = rest;
+        }
+
+        Ok(())
+    }
+}
+
+impl Persist for MemoryDelta {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.page_size.write(w)?;
+        self.uncompressed_len.write(w)?;
+        self.codec.write(w)?;
+        self.changed_pages.write(w)?;
+        write_bytes(w, &self.data)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            page_size: Persist::read(r)?,
+            uncompressed_len: Persist::read(r)?,
+            codec: Persist::read(r)?,
+            changed_pages: Persist::read(r)?,
+            data: read_bytes(r)?,
+        })
+    }
+}
+
+fn decompress_memories(snapshot: &Snapshot) -> Result<NamedVec<Vec<u8>>> {
+    snapshot
+        .memories
+        .iter()
+        .map(|(name, memory)| {
+            let mut data = vec![0; memory.uncompressed_len];
+            memory.decompress_into(&mut data)?;
+            Ok((name.clone(), data))
+        })
+        .collect()
+}
@@ -0,0 +1,94 @@
+use std::io::{Read, Write};
+
+use anyhow::{bail, ensure, Result};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use super::persist::Persist;
+
+/// How memory is compressed in a snapshot.
+///
+/// The codec is recorded in the snapshot, so it can be decompressed without
+/// knowing which options it was taken with.
+///
+/// The codecs are a closed set by design. Any host can read any snapshot
+/// without registering codecs first, and the tag for each codec is part of the
+/// snapshot format. To support a new codec, add a variant with a new tag.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Codec {
+    /// Store memory uncompressed.
+    None,
+    /// Deflate, with a compression level from `0` to `9`.
+    ///
+    /// This gives small snapshots, so it's good for archiving runs.
+    Deflate { level: u32 },
+    /// LZ4 block compression.
+    ///
+    /// This is much faster than [`Codec::Deflate`], so it's good for frequent
+    /// checkpoints.
+    Lz4,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self::Deflate {
+            level: Compression::default().level(),
+        }
+    }
+}
+
+impl Codec {
+    pub(super) fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::None => data.to_vec(),
+            Self::Deflate { level } => {
+                ensure!(level <= 9, "Deflate level {level} should be from 0 to 9");
+                let mut compressor = DeflateEncoder::new(Vec::new(), Compression::new(level));
+                compressor.write_all(data)?;
+                compressor.finish()?
+            }
+            Self::Lz4 => lz4_flex::block::compress(data),
+        })
+    }
+
+    /// Decompress `data`, which must decompress to exactly `output.len()`
+    /// bytes.
+    pub(super) fn decompress_into(self, data: &[u8], output: &mut [u8]) -> Result<()> {
+        match self {
+            Self::None => {
+                ensure!(data.len() == output.len(), "Unexpected data length");
+                output.copy_from_slice(data);
+            }
+            Self::Deflate { .. } => DeflateDecoder::new(data).read_exact(output)?,
+            Self::Lz4 => {
+                let len = lz4_flex::block::decompress_into(data, output)?;
+                ensure!(len == output.len(), "Unexpected data length");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Persist for Codec {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        match self {
+            Self::None => 0u8.write(w),
+            Self::Deflate { level } => {
+                1u8.write(w)?;
+                level.write(w)
+            }
+            Self::Lz4 => 2u8.write(w),
+        }
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(match u8::read(r)? {
+            0 => Self::None,
+            1 => Self::Deflate {
+                level: Persist::read(r)?,
+            },
+            2 => Self::Lz4,
+            tag => bail!("Invalid codec tag {tag}"),
+        })
+    }
+}
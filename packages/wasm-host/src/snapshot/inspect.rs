@@ -0,0 +1,299 @@
+//! Human readable summaries and diffs of snapshots, for debugging restores.
+use std::{collections::HashMap, fmt, ops::Range};
+
+use anyhow::Result;
+
+use super::{Globals, NamedVec, Snapshot, SnapshotMemory, TableEntry};
+
+impl Snapshot {
+    /// A summary of the globals, memories and tables in this snapshot.
+    pub fn summary(&self) -> Summary<'_> {
+        Summary(self)
+    }
+
+    /// Find the differences between `self` and `other`.
+    ///
+    /// This decompresses every memory in both snapshots.
+    pub fn diff(&self, other: &Self) -> Result<SnapshotDiff> {
+        let old_globals = self.globals.values();
+        let new_globals = other.globals.values();
+        let mut diff = SnapshotDiff::default();
+
+        for (name, change) in named_changes(&old_globals, &new_globals) {
+            if !matches!(change, Change::Changed(old, new) if old == new) {
+                diff.globals.push(format!("{name}: {change}"));
+            }
+        }
+
+        for (name, change) in named_changes(&self.memories, &other.memories) {
+            match change {
+                Change::Added(memory) => diff
+                    .memories
+                    .push(format!("{name}: added ({} bytes)", memory.uncompressed_len)),
+                Change::Removed(_) => diff.memories.push(format!("{name}: removed")),
+                Change::Changed(old, new) => diff.memories.extend(
+                    memory_changes(old, new)?
+                        .into_iter()
+                        .map(|change| format!("{name}: {change}")),
+                ),
+            }
+        }
+
+        for (name, change) in named_changes(&self.tables, &other.tables) {
+            match change {
+                Change::Added(table) => diff
+                    .tables
+                    .push(format!("{name}: added ({} slots)", table.len())),
+                Change::Removed(_) => diff.tables.push(format!("{name}: removed")),
+                Change::Changed(old, new) => diff.tables.extend(
+                    table_changes(old, new)
+                        .into_iter()
+                        .map(|change| format!("{name}: {change}")),
+                ),
+            }
+        }
+
+        Ok(diff)
+    }
+}
+
+/// See [`Snapshot::summary`].
+pub struct Summary<'a>(&'a Snapshot);
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let snapshot = self.0;
+
+        writeln!(f, "Globals:")?;
+
+        for (name, value) in snapshot.globals.values() {
+            writeln!(f, "    {name} = {value}")?;
+        }
+
+        writeln!(f, "Memories:")?;
+
+        for (name, memory) in &snapshot.memories {
+            writeln!(
+                f,
+                "    {name}: page size {}, {} bytes uncompressed, {} bytes stored ({:?})",
+                memory.page_size,
+                memory.uncompressed_len,
+                memory.data.len(),
+                memory.codec
+            )?;
+        }
+
+        writeln!(f, "Tables:")?;
+
+        for (name, table) in &snapshot.tables {
+            writeln!(f, "    {name}: {} slots", table.len())?;
+
+            for (slot, entry) in table.iter().enumerate() {
+                if let TableEntry::Func(Some(func_name)) = entry {
+                    writeln!(f, "        [{slot}] {func_name}")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The differences between two snapshots. See [`Snapshot::diff`].
+#[derive(Default)]
+pub struct SnapshotDiff {
+    globals: Vec<String>,
+    memories: Vec<String>,
+    tables: Vec<String>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.globals.is_empty() && self.memories.is_empty() && self.tables.is_empty()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (heading, changes) in [
+            ("Globals", &self.globals),
+            ("Memories", &self.memories),
+            ("Tables", &self.tables),
+        ] {
+            if !changes.is_empty() {
+                writeln!(f, "{heading}:")?;
+
+                for change in changes {
+                    writeln!(f, "    {change}")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Globals {
+    /// The name and formatted value of each global.
+    fn values(&self) -> NamedVec<String> {
+        let mut values = Vec::new();
+
+        format_values(&self.i32s, &mut values, |val| format!("i32 {val}"));
+        format_values(&self.i64s, &mut values, |val| format!("i64 {val}"));
+        format_values(&self.f32s, &mut values, |val| format!("f32 {val:?}"));
+        format_values(&self.f64s, &mut values, |val| format!("f64 {val:?}"));
+        format_values(&self.v128s, &mut values, |val| format!("v128 {val:#034x}"));
+        format_values(&self.functions, &mut values, |func_name| {
+            TableEntry::Func(func_name.clone()).to_string()
+        });
+        format_values(&self.extern_refs, &mut values, |handle_id| {
+            TableEntry::ExternRef(handle_id.clone()).to_string()
+        });
+
+        for name in &self.null_extern_ref_names {
+            values.push((name.clone(), TableEntry::NullExternRef.to_string()));
+        }
+
+        for name in &self.null_any_ref_names {
+            values.push((name.clone(), TableEntry::NullAnyRef.to_string()));
+        }
+
+        values.sort_by(|(name0, _), (name1, _)| name0.cmp(name1));
+        values
+    }
+}
+
+fn format_values<T>(
+    values: &NamedVec<T>,
+    formatted: &mut NamedVec<String>,
+    format: impl Fn(&T) -> String,
+) {
+    for (name, value) in values {
+        formatted.push((name.clone(), format(value)));
+    }
+}
+
+impl fmt::Display for TableEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Func(Some(func_name)) => write!(f, "func {func_name}"),
+            Self::Func(None) => write!(f, "null funcref"),
+            Self::ExternRef(handle_id) => write!(f, "externref {handle_id}"),
+            Self::NullExternRef => write!(f, "null externref"),
+            Self::NullAnyRef => write!(f, "null anyref"),
+        }
+    }
+}
+
+enum Change<'a, T> {
+    Added(&'a T),
+    Removed(&'a T),
+    Changed(&'a T, &'a T),
+}
+
+impl<T: fmt::Display> fmt::Display for Change<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added(new) => write!(f, "added ({new})"),
+            Self::Removed(old) => write!(f, "removed ({old})"),
+            Self::Changed(old, new) => write!(f, "{old} -> {new}"),
+        }
+    }
+}
+
+/// Match up items by name.
+///
+/// Items that are in both `old` and `new` are reported as
+/// [`Change::Changed`], even if they're equal.
+fn named_changes<'a, T>(
+    old: &'a NamedVec<T>,
+    new: &'a NamedVec<T>,
+) -> Vec<(&'a str, Change<'a, T>)> {
+    let new_by_name: HashMap<&str, &T> = new
+        .iter()
+        .map(|(name, item)| (name.as_str(), item))
+        .collect();
+    let mut changes = Vec::new();
+
+    for (name, old_item) in old {
+        match new_by_name.get(name.as_str()) {
+            Some(new_item) => changes.push((name.as_str(), Change::Changed(old_item, *new_item))),
+            None => changes.push((name.as_str(), Change::Removed(old_item))),
+        }
+    }
+
+    for (name, new_item) in new {
+        if !old.iter().any(|(old_name, _)| old_name == name) {
+            changes.push((name.as_str(), Change::Added(new_item)));
+        }
+    }
+
+    changes
+}
+
+fn memory_changes(old: &SnapshotMemory, new: &SnapshotMemory) -> Result<Vec<String>> {
+    let mut changes = Vec::new();
+
+    if old.page_size != new.page_size {
+        changes.push(format!("page size {} -> {}", old.page_size, new.page_size));
+        return Ok(changes);
+    }
+
+    if old.uncompressed_len != new.uncompressed_len {
+        changes.push(format!(
+            "length {} -> {} bytes",
+            old.uncompressed_len, new.uncompressed_len
+        ));
+    }
+
+    let mut old_data = vec![0; old.uncompressed_len];
+    old.decompress_into(&mut old_data)?;
+    let mut new_data = vec![0; new.uncompressed_len];
+    new.decompress_into(&mut new_data)?;
+
+    let page_size = usize::try_from(old.page_size)?;
+    let mut differing_pages: Vec<Range<usize>> = Vec::new();
+
+    // Pages past the end of the shorter memory are covered by the length
+    // change.
+    for (page, (old_page, new_page)) in old_data
+        .chunks(page_size)
+        .zip(new_data.chunks(page_size))
+        .enumerate()
+    {
+        if old_page != new_page {
+            match differing_pages.last_mut() {
+                Some(last) if last.end == page => last.end = page + 1,
+                _ => differing_pages.push(page..page + 1),
+            }
+        }
+    }
+
+    for pages in differing_pages {
+        if pages.len() == 1 {
+            changes.push(format!("page {} differs", pages.start));
+        } else {
+            changes.push(format!("pages {}..{} differ", pages.start, pages.end));
+        }
+    }
+
+    Ok(changes)
+}
+
+fn table_changes(old: &[TableEntry], new: &[TableEntry]) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    if old.len() != new.len() {
+        changes.push(format!("length {} -> {} slots", old.len(), new.len()));
+    }
+
+    for (slot, (old_entry, new_entry)) in old.iter().zip(new).enumerate() {
+        let (old_entry, new_entry) = (old_entry.to_string(), new_entry.to_string());
+
+        if old_entry != new_entry {
+            changes.push(format!("[{slot}] {old_entry} -> {new_entry}"));
+        }
+    }
+
+    changes
+}
@@ -0,0 +1,475 @@
+//! Checks for restoring a snapshot into a different build of the guest.
+//!
+//! Snapshots refer to globals, memories, tables and functions by their exported
+//! names, which are stable across builds where possible (see
+//! [`instrument`][crate::instrument::instrument]). So a snapshot can be
+//! restored into a patched guest, as long as every item it refers to still
+//! exists with a compatible type, and static data hasn't moved. Static data
+//! that changed in place is re-initialized from the new build.
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{Read, Write},
+};
+
+use anyhow::{Context, Result};
+use wasmtime::{AsContextMut, Instance, Val, ValType};
+
+use super::{
+    persist::{read_bytes, write_bytes, Persist},
+    Globals, NamedVec, Snapshot, TableEntry,
+};
+use crate::fingerprint::Hash;
+
+/// Addresses and static data that determine the layout of linear memory.
+///
+/// The addresses are the values of exported, immutable integer globals, such
+/// as `__data_end` and `__heap_base`. If any of them change between builds,
+/// static data has probably moved, so pointers in a snapshot's memory would be
+/// wrong.
+///
+/// Static data can still change in place, for example if a string literal is
+/// edited. A snapshot's memory holds the static data from the build it was
+/// taken from, so we record a hash of each active data segment, and
+/// [`StaticData::restore_changed`] re-initializes any that differ.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct MemoryLayout {
+    addresses: NamedVec<i64>,
+    /// The offset and hash of each active data segment, by memory name.
+    static_data: NamedVec<(u64, Hash)>,
+}
+
+impl MemoryLayout {
+    pub fn new(ctx: &mut impl AsContextMut, instance: &Instance, static_data: &StaticData) -> Self {
+        let exported_names: Vec<String> = instance
+            .exports(&mut *ctx)
+            .map(|e| e.name().to_string())
+            .collect();
+        let mut addresses = Vec::new();
+
+        for name in exported_names {
+            let Some(global) = instance.get_global(&mut *ctx, &name) else {
+                continue;
+            };
+
+            if global.ty(&mut *ctx).mutability().is_const() {
+                match global.get(&mut *ctx) {
+                    Val::I32(address) => addresses.push((name, address.into())),
+                    Val::I64(address) => addresses.push((name, address)),
+                    _ => (),
+                }
+            }
+        }
+
+        Self {
+            addresses,
+            static_data: static_data.0.iter().map(DataSegment::digest).collect(),
+        }
+    }
+
+    /// Check a snapshot taken with this layout can be restored into a guest
+    /// with the `new` layout.
+    pub fn check_migration(&self, new: &Self, report: &mut MigrationReport) {
+        for (name, old_address) in &self.addresses {
+            match new.addresses.iter().find(|(new_name, _)| new_name == name) {
+                Some((_, new_address)) if new_address == old_address => (),
+                Some((_, new_address)) => report.mismatches.push(format!(
+                    "Memory layout: '{name}' moved from {old_address:#x} to {new_address:#x}"
+                )),
+                None => report
+                    .mismatches
+                    .push(format!("Memory layout: '{name}' is missing")),
+            }
+        }
+
+        for (name, _) in &new.addresses {
+            if !self.addresses.iter().any(|(old_name, _)| old_name == name) {
+                report
+                    .mismatches
+                    .push(format!("Memory layout: '{name}' isn't in the snapshot"));
+            }
+        }
+
+        for segment in &new.static_data {
+            if !self.static_data.contains(segment) {
+                let (memory, (offset, _)) = segment;
+                report.warnings.push(format!(
+                    "Static data at {offset:#x} in memory '{memory}' differs from the snapshot, so \
+                     it's reset to this build's data"
+                ));
+            }
+        }
+    }
+}
+
+impl Persist for MemoryLayout {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.addresses.write(w)?;
+        self.static_data.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            addresses: Persist::read(r)?,
+            static_data: Persist::read(r)?,
+        })
+    }
+}
+
+/// The active data segments that initialize a build's memories.
+///
+/// Segments with offsets that aren't constant, such as in position
+/// independent code, aren't included.
+#[derive(Clone, Default, Debug)]
+pub struct StaticData(Vec<DataSegment>);
+
+impl StaticData {
+    pub(crate) fn new(segments: Vec<DataSegment>) -> Self {
+        Self(segments)
+    }
+
+    /// Re-initialize each data segment that isn't in `layout`.
+    ///
+    /// `layout` is from a snapshot that's been restored into `instance`, so
+    /// the memory has the static data of the snapshot's build. This updates it
+    /// to match this build.
+    pub fn restore_changed(
+        &self,
+        layout: &MemoryLayout,
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+    ) -> Result<()> {
+        for segment in &self.0 {
+            if layout.static_data.contains(&segment.digest()) {
+                continue;
+            }
+
+            let name = &segment.memory;
+            let memory = instance
+                .get_memory(&mut *ctx, name)
+                .with_context(|| format!("Couldn't find memory '{name}'"))?;
+            memory
+                .write(&mut *ctx, segment.offset.try_into()?, &segment.data)
+                .with_context(|| format!("Static data is out of bounds in memory '{name}'"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An active data segment.
+#[derive(Clone, Debug)]
+pub(crate) struct DataSegment {
+    /// The exported name of the memory the segment initializes.
+    pub memory: String,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+impl DataSegment {
+    fn digest(&self) -> (String, (u64, Hash)) {
+        (self.memory.clone(), (self.offset, Hash::new(&self.data)))
+    }
+}
+
+impl Persist for StaticData {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.0.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self(Persist::read(r)?))
+    }
+}
+
+impl Persist for DataSegment {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.memory.write(w)?;
+        self.offset.write(w)?;
+        write_bytes(w, &self.data)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            memory: Persist::read(r)?,
+            offset: Persist::read(r)?,
+            data: read_bytes(r)?,
+        })
+    }
+}
+
+/// Differences between a snapshot and the guest it's being restored into.
+#[derive(Default, Debug)]
+pub struct MigrationReport {
+    /// Differences that would corrupt the guest if the snapshot was restored.
+    pub mismatches: Vec<String>,
+    /// Differences that are safe, but may change the guest's behavior.
+    ///
+    /// For example, a global that's not in the snapshot keeps its initial
+    /// value.
+    pub warnings: Vec<String>,
+}
+
+impl MigrationReport {
+    pub fn is_compatible(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for mismatch in &self.mismatches {
+            writeln!(f, "Mismatch: {mismatch}")?;
+        }
+
+        for warning in &self.warnings {
+            writeln!(f, "Warning: {warning}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    /// Check this snapshot can be restored into `instance`, which may be from
+    /// a different build of the guest.
+    ///
+    /// This doesn't check memory layout. See [`MemoryLayout`].
+    pub fn check_migration(
+        &self,
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+        report: &mut MigrationReport,
+    ) {
+        self.globals.check_migration(ctx, instance, report);
+        self.check_memories(ctx, instance, report);
+        self.check_tables(ctx, instance, report);
+
+        let mut func_names = BTreeSet::new();
+        func_names.extend(
+            self.globals
+                .functions
+                .iter()
+                .filter_map(|(_, f)| f.as_ref()),
+        );
+
+        for (_, table) in &self.tables {
+            func_names.extend(table.iter().filter_map(|entry| match entry {
+                TableEntry::Func(func_name) => func_name.as_ref(),
+                _ => None,
+            }));
+        }
+
+        for func_name in func_names {
+            if instance.get_func(&mut *ctx, func_name).is_none() {
+                report
+                    .mismatches
+                    .push(format!("Function '{func_name}' is missing"));
+            }
+        }
+
+        let exported_names: Vec<String> = instance
+            .exports(&mut *ctx)
+            .map(|e| e.name().to_string())
+            .collect();
+        let global_names = self.globals.names();
+
+        for name in exported_names {
+            if let Some(global) = instance.get_global(&mut *ctx, &name) {
+                if global.ty(&mut *ctx).mutability().is_var() && !global_names.contains(&name) {
+                    report.warnings.push(format!(
+                        "Global '{name}' isn't in the snapshot, so it keeps its initial value"
+                    ));
+                }
+            }
+
+            let is_memory = instance.get_memory(&mut *ctx, &name).is_some()
+                || instance.get_shared_memory(&mut *ctx, &name).is_some();
+
+            if is_memory && !self.memories.iter().any(|(n, _)| *n == name) {
+                report.warnings.push(format!(
+                    "Memory '{name}' isn't in the snapshot, so it keeps its initial contents"
+                ));
+            }
+
+            if instance.get_table(&mut *ctx, &name).is_some()
+                && !self.tables.iter().any(|(n, _)| *n == name)
+            {
+                report.warnings.push(format!(
+                    "Table '{name}' isn't in the snapshot, so it keeps its initial contents"
+                ));
+            }
+        }
+    }
+
+    fn check_memories(
+        &self,
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+        report: &mut MigrationReport,
+    ) {
+        for (name, snapshot) in &self.memories {
+            let ty = if let Some(memory) = instance.get_memory(&mut *ctx, name) {
+                memory.ty(&*ctx)
+            } else if instance.get_shared_memory(&mut *ctx, name).is_some() {
+                report.mismatches.push(format!(
+                    "Memory '{name}' is shared, and shared memories can't be restored"
+                ));
+                continue;
+            } else {
+                report
+                    .mismatches
+                    .push(format!("Memory '{name}' is missing"));
+                continue;
+            };
+
+            let page_size = ty.page_size();
+
+            if page_size != snapshot.page_size {
+                report.mismatches.push(format!(
+                    "Memory '{name}' has page size {page_size}, but the snapshot has {}",
+                    snapshot.page_size
+                ));
+                continue;
+            }
+
+            if let Some(maximum) = ty.maximum() {
+                let max_len = maximum.saturating_mul(page_size);
+
+                if u64::try_from(snapshot.uncompressed_len).map_or(true, |len| len > max_len) {
+                    report.mismatches.push(format!(
+                        "Memory '{name}' can grow to {max_len} bytes, but the snapshot has {}",
+                        snapshot.uncompressed_len
+                    ));
+                }
+            }
+        }
+    }
+
+    fn check_tables(
+        &self,
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+        report: &mut MigrationReport,
+    ) {
+        for (name, snapshot_table) in &self.tables {
+            let Some(table) = instance.get_table(&mut *ctx, name) else {
+                report.mismatches.push(format!("Table '{name}' is missing"));
+                continue;
+            };
+
+            let ty = table.ty(&*ctx);
+
+            if let Some(maximum) = ty.maximum() {
+                if u64::try_from(snapshot_table.len()).map_or(true, |len| len > maximum) {
+                    report.mismatches.push(format!(
+                        "Table '{name}' can grow to {maximum} slots, but the snapshot has {}",
+                        snapshot_table.len()
+                    ));
+                }
+            }
+
+            let element = ValType::Ref(ty.element().clone());
+
+            if let Some((slot, entry)) =
+                snapshot_table
+                    .iter()
+                    .enumerate()
+                    .find(|(_, entry)| !match entry {
+                        TableEntry::Func(_) => element.is_funcref(),
+                        TableEntry::ExternRef(_) | TableEntry::NullExternRef => {
+                            element.is_externref()
+                        }
+                        TableEntry::NullAnyRef => element.is_anyref(),
+                    })
+            {
+                report.mismatches.push(format!(
+                    "Table '{name}' has element type {element}, but slot {slot} in the snapshot \
+                     is {entry}"
+                ));
+            }
+        }
+    }
+}
+
+/// A global's name, the name of its type, and a check for its type.
+type GlobalType<'a> = (&'a str, &'static str, fn(&ValType) -> bool);
+
+impl Globals {
+    fn check_migration(
+        &self,
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+        report: &mut MigrationReport,
+    ) {
+        for (name, expected_type, is_expected_type) in self.types() {
+            let Some(global) = instance.get_global(&mut *ctx, name) else {
+                report
+                    .mismatches
+                    .push(format!("Global '{name}' is missing"));
+                continue;
+            };
+            let ty = global.ty(&mut *ctx);
+
+            if ty.mutability().is_const() {
+                report
+                    .mismatches
+                    .push(format!("Global '{name}' is immutable"));
+            }
+
+            if !is_expected_type(ty.content()) {
+                report.mismatches.push(format!(
+                    "Global '{name}' has type {}, but the snapshot has {expected_type}",
+                    ty.content()
+                ));
+            }
+        }
+    }
+
+    fn names(&self) -> BTreeSet<String> {
+        self.types()
+            .into_iter()
+            .map(|(name, _, _)| name.to_string())
+            .collect()
+    }
+
+    /// The name of each global, along with its type and a check for it.
+    fn types(&self) -> Vec<GlobalType<'_>> {
+        fn add<'a, T>(
+            types: &mut Vec<GlobalType<'a>>,
+            values: &'a NamedVec<T>,
+            type_name: &'static str,
+            is_type: fn(&ValType) -> bool,
+        ) {
+            types.extend(
+                values
+                    .iter()
+                    .map(|(name, _)| (name.as_str(), type_name, is_type)),
+            );
+        }
+
+        let mut types = Vec::new();
+        add(&mut types, &self.i32s, "i32", ValType::is_i32);
+        add(&mut types, &self.i64s, "i64", ValType::is_i64);
+        add(&mut types, &self.f32s, "f32", ValType::is_f32);
+        add(&mut types, &self.f64s, "f64", ValType::is_f64);
+        add(&mut types, &self.v128s, "v128", ValType::is_v128);
+        add(&mut types, &self.functions, "funcref", ValType::is_funcref);
+        add(
+            &mut types,
+            &self.extern_refs,
+            "externref",
+            ValType::is_externref,
+        );
+
+        for name in &self.null_extern_ref_names {
+            types.push((name.as_str(), "externref", ValType::is_externref));
+        }
+
+        for name in &self.null_any_ref_names {
+            types.push((name.as_str(), "anyref", ValType::is_anyref));
+        }
+
+        types
+    }
+}
@@ -0,0 +1,228 @@
+//! A simple, versioned binary format for persisting snapshots.
+//!
+//! All integers are little endian. Collections and strings are prefixed with
+//! their length as a `u64`.
+use std::{
+    io::{Read, Write},
+    ops::Range,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+
+const MAGIC: [u8; 8] = *b"CDSTSNAP";
+const FORMAT_VERSION: u32 = 1;
+
+/// What a persisted snapshot contains.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SnapshotKind {
+    /// A [`Snapshot`][super::Snapshot] of the WASM state.
+    Wasm,
+    /// A [`ContainerSnapshot`][crate::runtime::ContainerSnapshot].
+    Container,
+    /// A [`SnapshotChain`][super::chain::SnapshotChain].
+    Chain,
+    /// A [`ContainerSnapshotChain`][crate::runtime::ContainerSnapshotChain].
+    ContainerChain,
+}
+
+impl Persist for SnapshotKind {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        match self {
+            Self::Wasm => 0u8,
+            Self::Container => 1u8,
+            Self::Chain => 2u8,
+            Self::ContainerChain => 3u8,
+        }
+        .write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(match u8::read(r)? {
+            0 => Self::Wasm,
+            1 => Self::Container,
+            2 => Self::Chain,
+            3 => Self::ContainerChain,
+            tag => bail!("Invalid snapshot kind {tag}"),
+        })
+    }
+}
+
+pub fn write_header(w: &mut impl Write, kind: SnapshotKind) -> Result<()> {
+    w.write_all(&MAGIC)?;
+    FORMAT_VERSION.write(w)?;
+    kind.write(w)
+}
+
+pub fn read_header(r: &mut impl Read, expected_kind: SnapshotKind) -> Result<()> {
+    let kind = read_kind(r)?;
+    ensure!(
+        kind == expected_kind,
+        "Expected a {expected_kind:?} snapshot, but found a {kind:?} snapshot"
+    );
+
+    Ok(())
+}
+
+/// Read the header, and return the kind of snapshot that follows it.
+pub fn read_kind(r: &mut impl Read) -> Result<SnapshotKind> {
+    let mut magic = [0; MAGIC.len()];
+    r.read_exact(&mut magic)
+        .context("Reading snapshot header")?;
+    ensure!(magic == MAGIC, "Not a codastraea snapshot");
+    let version = u32::read(r)?;
+
+    if version != FORMAT_VERSION {
+        bail!("Unsupported snapshot format version {version} (expected {FORMAT_VERSION})");
+    }
+
+    SnapshotKind::read(r)
+}
+
+pub trait Persist: Sized {
+    fn write(&self, w: &mut impl Write) -> Result<()>;
+
+    fn read(r: &mut impl Read) -> Result<Self>;
+}
+
+macro_rules! persist_le_bytes {
+    ($($ty:ty),*) => {
+        $(
+            impl Persist for $ty {
+                fn write(&self, w: &mut impl Write) -> Result<()> {
+                    w.write_all(&self.to_le_bytes())?;
+                    Ok(())
+                }
+
+                fn read(r: &mut impl Read) -> Result<Self> {
+                    let mut bytes = [0; size_of::<$ty>()];
+                    r.read_exact(&mut bytes)?;
+                    Ok(Self::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+persist_le_bytes!(u8, u32, u64, u128, i32, i64, f32, f64);
+
+impl Persist for usize {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        u64::try_from(*self)?.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(u64::read(r)?.try_into()?)
+    }
+}
+
+impl Persist for bool {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        u8::from(*self).write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        match u8::read(r)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => bail!("Invalid bool {value}"),
+        }
+    }
+}
+
+impl Persist for String {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.len().write(w)?;
+        w.write_all(self.as_bytes())?;
+        Ok(())
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        let bytes = read_bytes(r)?;
+        String::from_utf8(bytes).context("Invalid utf-8")
+    }
+}
+
+impl<T: Persist> Persist for Option<T> {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.is_some().write(w)?;
+
+        if let Some(value) = self {
+            value.write(w)?;
+        }
+
+        Ok(())
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(if bool::read(r)? {
+            Some(T::read(r)?)
+        } else {
+            None
+        })
+    }
+}
+
+impl<T: Persist> Persist for Vec<T> {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.len().write(w)?;
+
+        for item in self {
+            item.write(w)?;
+        }
+
+        Ok(())
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        let len = usize::read(r)?;
+        // Don't trust `len` to preallocate, as the input could be corrupt.
+        let mut items = Vec::new();
+
+        for _ in 0..len {
+            items.push(T::read(r)?);
+        }
+
+        Ok(items)
+    }
+}
+
+impl<S: Persist, T: Persist> Persist for (S, T) {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.0.write(w)?;
+        self.1.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok((S::read(r)?, T::read(r)?))
+    }
+}
+
+impl Persist for Range<usize> {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.start.write(w)?;
+        self.end.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(usize::read(r)?..usize::read(r)?)
+    }
+}
+
+/// Write a length prefixed byte buffer.
+pub fn write_bytes(w: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    bytes.len().write(w)?;
+    w.write_all(bytes)?;
+    Ok(())
+}
+
+/// Read a length prefixed byte buffer.
+pub fn read_bytes(r: &mut impl Read) -> Result<Vec<u8>> {
+    let len = u64::read(r)?;
+    let mut bytes = Vec::new();
+    r.take(len).read_to_end(&mut bytes)?;
+    ensure!(
+        u64::try_from(bytes.len())? == len,
+        "Unexpected end of snapshot data"
+    );
+    Ok(bytes)
+}
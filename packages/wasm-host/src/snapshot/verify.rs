@@ -0,0 +1,221 @@
+//! Digests for checking a restore reproduced the snapshotted state.
+use std::io::{Read, Write};
+
+use anyhow::{bail, Result};
+use wasmtime::{AsContextMut, Instance};
+
+use super::{
+    capture, persist::Persist, Globals, NamedVec, SharedMemoryPolicy, SnapshotOptions, TableEntry,
+};
+use crate::fingerprint::Hash;
+
+/// Digests of each global, memory and table in an instance.
+#[derive(Clone, Eq, PartialEq)]
+pub(super) struct Digests {
+    globals: NamedVec<Hash>,
+    memories: NamedVec<LenDigest>,
+    tables: NamedVec<LenDigest>,
+}
+
+impl Digests {
+    pub fn new(ctx: &mut impl AsContextMut, instance: &Instance) -> Result<Self> {
+        Self::capture(ctx, instance, None)
+    }
+
+    /// Digest `instance`.
+    ///
+    /// Memories and tables can't shrink, so a restore can leave them longer
+    /// than they were in the snapshot. If they're in `expected`, and
+    /// everything past their expected length is zero or null, only their
+    /// expected length is digested.
+    fn capture(
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+        expected: Option<&Self>,
+    ) -> Result<Self> {
+        // If the snapshot contains a shared memory, it was taken with
+        // `SharedMemoryPolicy::Copy`.
+        let (globals, memories, tables) = capture(
+            ctx,
+            instance,
+            SharedMemoryPolicy::Copy,
+            |name, _page_size, data| {
+                let len = digest_len(
+                    data,
+                    expected_len(expected, name, |e| &e.memories),
+                    |&byte| byte == 0,
+                );
+                Ok(LenDigest {
+                    len,
+                    hash: Hash::new(&data[..len]),
+                })
+            },
+        )?;
+
+        Ok(Self {
+            globals: globals.digests()?,
+            memories,
+            tables: tables
+                .into_iter()
+                .map(|(name, mut table)| {
+                    let expected_len = expected_len(expected, &name, |e| &e.tables);
+                    let len = digest_len(&table, expected_len, TableEntry::is_null);
+                    table.truncate(len);
+                    let hash = digest(&table)?;
+                    Ok((name, LenDigest { len, hash }))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn optional(
+        ctx: &mut impl AsContextMut,
+        instance: &Instance,
+        options: &SnapshotOptions,
+    ) -> Result<Option<Self>> {
+        options.verify.then(|| Self::new(ctx, instance)).transpose()
+    }
+
+    /// Check `instance` matches these digests.
+    ///
+    /// The error lists every global, memory and table that diverged.
+    pub fn verify(&self, ctx: &mut impl AsContextMut, instance: &Instance) -> Result<()> {
+        let actual = Self::capture(ctx, instance, Some(self))?;
+        let mut diverged = Vec::new();
+
+        diverged_items("Global", &self.globals, &actual.globals, &mut diverged);
+        diverged_items("Memory", &self.memories, &actual.memories, &mut diverged);
+        diverged_items("Table", &self.tables, &actual.tables, &mut diverged);
+
+        if !diverged.is_empty() {
+            bail!(
+                "Restored state doesn't match the snapshot:\n{}",
+                diverged.join("\n")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Persist for Digests {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.globals.write(w)?;
+        self.memories.write(w)?;
+        self.tables.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            globals: Persist::read(r)?,
+            memories: Persist::read(r)?,
+            tables: Persist::read(r)?,
+        })
+    }
+}
+
+/// A digest of the first `len` bytes of a memory, or entries of a table.
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct LenDigest {
+    len: usize,
+    hash: Hash,
+}
+
+impl Persist for LenDigest {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.len.write(w)?;
+        self.hash.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            len: Persist::read(r)?,
+            hash: Persist::read(r)?,
+        })
+    }
+}
+
+/// The expected length of memory or table `name`, if `expected` has it.
+fn expected_len(
+    expected: Option<&Digests>,
+    name: &str,
+    items: impl FnOnce(&Digests) -> &NamedVec<LenDigest>,
+) -> Option<usize> {
+    let (_, digest) = items(expected?)
+        .iter()
+        .find(|(item_name, _)| item_name == name)?;
+    Some(digest.len)
+}
+
+/// How many of `items` to digest.
+///
+/// This is `expected_len` if everything after that is empty, otherwise it's
+/// every item, so the digest won't match.
+fn digest_len<T>(items: &[T], expected_len: Option<usize>, is_empty: impl Fn(&T) -> bool) -> usize {
+    match expected_len {
+        Some(len) if len <= items.len() && items[len..].iter().all(is_empty) => len,
+        _ => items.len(),
+    }
+}
+
+impl Globals {
+    fn digests(&self) -> Result<NamedVec<Hash>> {
+        let mut digests = Vec::new();
+
+        named_digests(&self.i32s, &mut digests)?;
+        named_digests(&self.i64s, &mut digests)?;
+        named_digests(&self.f32s, &mut digests)?;
+        named_digests(&self.f64s, &mut digests)?;
+        named_digests(&self.v128s, &mut digests)?;
+        named_digests(&self.functions, &mut digests)?;
+        named_digests(&self.extern_refs, &mut digests)?;
+
+        for name in self
+            .null_extern_ref_names
+            .iter()
+            .chain(&self.null_any_ref_names)
+        {
+            digests.push((name.clone(), Hash::new(&[])));
+        }
+
+        Ok(digests)
+    }
+}
+
+fn named_digests<T: Persist>(values: &NamedVec<T>, digests: &mut NamedVec<Hash>) -> Result<()> {
+    for (name, value) in values {
+        digests.push((name.clone(), digest(value)?));
+    }
+
+    Ok(())
+}
+
+fn digest(value: &impl Persist) -> Result<Hash> {
+    let mut bytes = Vec::new();
+    value.write(&mut bytes)?;
+    Ok(Hash::new(&bytes))
+}
+
+fn diverged_items<T: Eq>(
+    item_type: &str,
+    expected: &NamedVec<T>,
+    actual: &NamedVec<T>,
+    diverged: &mut Vec<String>,
+) {
+    for (name, expected_hash) in expected {
+        match actual.iter().find(|(actual_name, _)| actual_name == name) {
+            Some((_, actual_hash)) if actual_hash == expected_hash => (),
+            Some(_) => diverged.push(format!("{item_type} '{name}' has different contents")),
+            None => diverged.push(format!("{item_type} '{name}' is missing")),
+        }
+    }
+
+    for (name, _) in actual {
+        if !expected
+            .iter()
+            .any(|(expected_name, _)| expected_name == name)
+        {
+            diverged.push(format!("{item_type} '{name}' is not in the snapshot"));
+        }
+    }
+}
@@ -1,6 +1,11 @@
-use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    mem,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
 
-use anyhow::{ensure, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use codastraea_server_api::{
     CallTreeChildNodeId, CallTreeNodeId, NewNode, NodeStatus, NodeType, NodeVecDiff,
 };
@@ -9,7 +14,9 @@ use futures::{
     Stream,
 };
 use futures_channel::mpsc;
-use slotmap::SlotMap;
+use slotmap::{Key, KeyData};
+
+use crate::snapshot::persist::Persist;
 
 #[derive(Clone)]
 pub struct NodeStore(Arc<RwLock<NodeStoreData>>);
@@ -22,7 +29,7 @@ impl NodeStore {
         let data = self.0.read().unwrap();
 
         if let CallTreeNodeId::Child(child_id) = id {
-            if let Some(child) = data.children.get(child_id) {
+            if let Some(child) = data.children.get(&child_id) {
                 Box::pin(child.watch())
             } else {
                 Box::pin(stream::empty())
@@ -35,18 +42,47 @@ impl NodeStore {
     fn new(root: NodeVec) -> Self {
         Self(Arc::new(RwLock::new(NodeStoreData {
             root,
-            children: SlotMap::with_key(),
+            children: HashMap::new(),
+            next_id: 0,
         })))
     }
 
     fn insert(&self, nodes: NodeVec) -> CallTreeChildNodeId {
-        self.0.write().unwrap().children.insert(nodes)
+        let mut data = self.0.write().unwrap();
+        let id = KeyData::from_ffi(data.next_id).into();
+        data.next_id += 1;
+        data.children.insert(id, nodes);
+        id
+    }
+
+    /// Insert `nodes` with an `id` from a snapshot.
+    fn insert_with_id(&self, id: CallTreeChildNodeId, nodes: NodeVec) {
+        self.0.write().unwrap().children.insert(id, nodes);
+    }
+
+    fn root(&self) -> NodeVec {
+        self.0.read().unwrap().root.clone()
+    }
+
+    fn next_id(&self) -> u64 {
+        self.0.read().unwrap().next_id
+    }
+
+    /// Remove all the child nodes, and make sure new ids don't clash with ids
+    /// up to `next_id`.
+    fn take_children(&self, next_id: u64) -> HashMap<CallTreeChildNodeId, NodeVec> {
+        let mut data = self.0.write().unwrap();
+        data.next_id = data.next_id.max(next_id);
+        mem::take(&mut data.children)
     }
 }
 
 struct NodeStoreData {
     root: NodeVec,
-    children: SlotMap<CallTreeChildNodeId, NodeVec>,
+    children: HashMap<CallTreeChildNodeId, NodeVec>,
+    /// Ids are never reused, even after a restore, so watchers of nodes that
+    /// were removed can't end up watching a different node.
+    next_id: u64,
 }
 
 pub struct Thread {
@@ -70,23 +106,102 @@ impl Thread {
         self.node_store.clone()
     }
 
-    // TODO: Error handling for `fn_begin` and `fn_end`. We should really display an
-    // error node in the call tree.
-    pub fn begin(&mut self, typ: &NodeType) {
-        self.try_begin(typ).expect("TODO: Handle errors")
+    pub fn snapshot(&self) -> ThreadSnapshot {
+        ThreadSnapshot {
+            nodes: NodeSnapshot::from_nodes(&self.node_store.root()),
+            call_depth: self.call_stack.len(),
+            next_id: self.node_store.next_id(),
+        }
+    }
+
+    /// Restore the call tree and call stack from `snapshot`.
+    ///
+    /// The existing [`NodeStore`] is reused, and nodes keep the ids they had
+    /// when the snapshot was taken. Anything watching the root, or a node
+    /// that's in the snapshot, will see the restored nodes. Streams watching
+    /// nodes that aren't in the snapshot end.
+    pub fn restore(&mut self, snapshot: &ThreadSnapshot) -> Result<()> {
+        let mut removed = self.node_store.take_children(snapshot.next_id);
+        let root = self.node_store.root();
+        let nodes = self.restore_nodes(&snapshot.nodes, &mut removed);
+
+        for nodes in removed.values() {
+            nodes.write().watchers.clear();
+        }
+
+        {
+            let mut root_state = root.write();
+            root_state.notify(|| NodeVecDiff::Replace(nodes.iter().map(NewNode::from).collect()));
+            root_state.values = nodes;
+        }
+
+        let mut call_stack = vec![StackFrame::new(root)];
+
+        while call_stack.len() < snapshot.call_depth {
+            let top = &call_stack.last().expect("Call stack is not empty").nodes;
+            let top = top.read();
+            let running = top
+                .values
+                .last()
+                .context("Expected a running node for each stack frame")?;
+            ensure!(
+                running.status == NodeStatus::Running,
+                "Expected node to be running"
+            );
+            let sub_tree = running.sub_tree.clone();
+            drop(top);
+            call_stack.push(StackFrame::new(sub_tree));
+        }
+
+        self.call_stack = call_stack;
+
+        Ok(())
     }
 
-    pub fn end(&mut self, typ: &NodeType) {
-        self.try_end(typ).expect("TODO: Handle errors")
+    /// Restore nodes from `snapshots`, reusing node vecs from `existing` so
+    /// their watchers see the changes.
+    fn restore_nodes(
+        &self,
+        snapshots: &[NodeSnapshot],
+        existing: &mut HashMap<CallTreeChildNodeId, NodeVec>,
+    ) -> Vec<Node> {
+        snapshots
+            .iter()
+            .map(|snapshot| {
+                let id = KeyData::from_ffi(snapshot.id).into();
+                let sub_tree = existing.remove(&id).unwrap_or_default();
+                let children = self.restore_nodes(&snapshot.children, existing);
+
+                {
+                    let mut state = sub_tree.write();
+                    state.notify(|| {
+                        NodeVecDiff::Replace(children.iter().map(NewNode::from).collect())
+                    });
+                    state.values = children;
+                }
+
+                self.node_store.insert_with_id(id, sub_tree.clone());
+
+                Node {
+                    id,
+                    typ: snapshot.typ.clone(),
+                    status: snapshot.status,
+                    error: snapshot.error.clone(),
+                    sub_tree,
+                }
+            })
+            .collect()
     }
 
-    fn try_begin(&mut self, typ: &NodeType) -> Result<()> {
+    /// Start a node of type `typ`, as a child of the running node.
+    pub fn begin(&mut self, typ: &NodeType) -> Result<()> {
         let new_top = NodeVec::default();
         let id = self.node_store.insert(new_top.clone());
         let node = Node {
             id,
             typ: typ.clone(),
             status: NodeStatus::Running,
+            error: None,
             sub_tree: new_top.clone(),
         };
         let top = self.top_mut()?;
@@ -115,25 +230,69 @@ impl Thread {
         Ok(())
     }
 
-    fn try_end(&mut self, typ: &NodeType) -> Result<()> {
-        self.pop()?;
-        let mut nodes = self.top_mut()?.nodes.write();
-        let current = nodes
-            .values
-            .last_mut()
-            .context("There should be a node on the call stack")?;
-        ensure!(&current.typ == typ);
-        ensure!(current.status == NodeStatus::Running);
-        current.status = NodeStatus::Complete;
-        let last_index = nodes
+    /// Complete the running node, which must have type `typ`.
+    ///
+    /// This fails if the guest's `begin` and `end` calls don't match.
+    pub fn end(&mut self, typ: &NodeType) -> Result<()> {
+        let name = typ.as_snake_str();
+        let Some(parent_index) = self.call_stack.len().checked_sub(2) else {
+            bail!("Guest ended '{name}', but no node is running");
+        };
+        let mut nodes = self.call_stack[parent_index].nodes.write();
+        let index = nodes
             .len()
             .checked_sub(1)
             .context("There should be a node on the call stack")?;
+        let current = &mut nodes.values[index];
+        ensure!(
+            &current.typ == typ,
+            "Guest ended '{name}', but '{}' is running",
+            current.typ.as_snake_str()
+        );
+        ensure!(
+            current.status == NodeStatus::Running,
+            "Guest ended '{name}', but it isn't running"
+        );
+        current.status = NodeStatus::Complete;
         nodes.notify(|| NodeVecDiff::SetStatus {
-            index: last_index,
+            index,
             status: NodeStatus::Complete,
         });
-        Ok(())
+        drop(nodes);
+
+        self.pop()
+    }
+
+    /// Mark every running node as failed with `error`, and unwind the call
+    /// stack.
+    ///
+    /// Use this when the guest traps, as the `end` calls for running nodes
+    /// will never come.
+    pub fn fail(&mut self, error: &str) {
+        while self.call_stack.len() > 1 {
+            self.call_stack.pop();
+            let mut nodes = self
+                .call_stack
+                .last()
+                .expect("Call stack is not empty")
+                .nodes
+                .write();
+            let Some(index) = nodes.len().checked_sub(1) else {
+                continue;
+            };
+            let running = &mut nodes.values[index];
+
+            if running.status != NodeStatus::Running {
+                continue;
+            }
+
+            running.status = NodeStatus::Failed;
+            running.error = Some(error.to_string());
+            nodes.notify(|| NodeVecDiff::SetFailed {
+                index,
+                error: error.to_string(),
+            });
+        }
     }
 
     fn top_mut(&mut self) -> Result<&mut StackFrame> {
@@ -150,6 +309,129 @@ impl Thread {
     }
 }
 
+/// The call tree and call stack of a [`Thread`].
+#[derive(Clone)]
+pub struct ThreadSnapshot {
+    nodes: Vec<NodeSnapshot>,
+    call_depth: usize,
+    next_id: u64,
+}
+
+impl Persist for ThreadSnapshot {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.nodes.write(w)?;
+        self.call_depth.write(w)?;
+        self.next_id.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            nodes: Persist::read(r)?,
+            call_depth: Persist::read(r)?,
+            next_id: Persist::read(r)?,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct NodeSnapshot {
+    /// The node's [`CallTreeChildNodeId`], from [`KeyData::as_ffi`].
+    id: u64,
+    typ: NodeType,
+    status: NodeStatus,
+    error: Option<String>,
+    children: Vec<NodeSnapshot>,
+}
+
+impl NodeSnapshot {
+    fn from_nodes(nodes: &NodeVec) -> Vec<Self> {
+        nodes
+            .read()
+            .values
+            .iter()
+            .map(|node| Self {
+                id: node.id.data().as_ffi(),
+                typ: node.typ.clone(),
+                status: node.status,
+                error: node.error.clone(),
+                children: Self::from_nodes(&node.sub_tree),
+            })
+            .collect()
+    }
+}
+
+impl Persist for NodeSnapshot {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.id.write(w)?;
+        self.typ.write(w)?;
+        self.status.write(w)?;
+        self.error.write(w)?;
+        self.children.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            id: Persist::read(r)?,
+            typ: Persist::read(r)?,
+            status: Persist::read(r)?,
+            error: Persist::read(r)?,
+            children: Persist::read(r)?,
+        })
+    }
+}
+
+impl Persist for NodeType {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        match self {
+            Self::Call { name } => {
+                0u8.write(w)?;
+                name.write(w)
+            }
+            Self::If => 1u8.write(w),
+            Self::Condition => 2u8.write(w),
+            Self::Then => 3u8.write(w),
+            Self::ElseIf => 4u8.write(w),
+            Self::Else => 5u8.write(w),
+        }
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(match u8::read(r)? {
+            0 => Self::Call {
+                name: Persist::read(r)?,
+            },
+            1 => Self::If,
+            2 => Self::Condition,
+            3 => Self::Then,
+            4 => Self::ElseIf,
+            5 => Self::Else,
+            tag => bail!("Invalid node type tag {tag}"),
+        })
+    }
+}
+
+impl Persist for NodeStatus {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        match self {
+            Self::NotRun => 0u8,
+            Self::Running => 1u8,
+            Self::Complete => 2u8,
+            Self::Failed => 3u8,
+        }
+        .write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(match u8::read(r)? {
+            0 => Self::NotRun,
+            1 => Self::Running,
+            2 => Self::Complete,
+            3 => Self::Failed,
+            tag => bail!("Invalid node status tag {tag}"),
+        })
+    }
+}
+
 #[derive(Default)]
 struct NodeVecState {
     values: Vec<Node>,
@@ -180,11 +462,11 @@ impl NodeVecState {
 struct NodeVec(Arc<RwLock<NodeVecState>>);
 
 impl NodeVec {
-    fn read(&self) -> RwLockReadGuard<NodeVecState> {
+    fn read(&self) -> RwLockReadGuard<'_, NodeVecState> {
         self.0.read().unwrap()
     }
 
-    fn write(&self) -> RwLockWriteGuard<NodeVecState> {
+    fn write(&self) -> RwLockWriteGuard<'_, NodeVecState> {
         self.0.write().unwrap()
     }
 
@@ -221,6 +503,7 @@ struct Node {
     id: CallTreeChildNodeId,
     typ: NodeType,
     status: NodeStatus,
+    error: Option<String>,
     sub_tree: NodeVec,
 }
 
@@ -231,6 +514,7 @@ impl<'a> From<&'a Node> for NewNode {
             id: value.id,
             typ: value.typ.clone(),
             status: value.status,
+            error: value.error.clone(),
             has_children,
         }
     }
@@ -0,0 +1,369 @@
+//! Sandboxed, deterministic WASI support for guests.
+//!
+//! Core modules get WASI preview 1, and components get WASI preview 2.
+//!
+//! Each guest gets its own preopened workspace directory, and its stdout and
+//! stderr are captured. Nothing else from the host is visible. Clocks and
+//! randomness are recorded the first time the guest reads them, and replayed
+//! after a restore, so a restored guest sees the same values it saw when it
+//! first ran.
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use rand_core::{OsRng, RngCore};
+use wasmtime::Linker;
+use wasmtime_wasi::{
+    p2::{pipe::MemoryOutputPipe, WasiCtx, WasiCtxBuilder},
+    preview1::WasiP1Ctx,
+    DirPerms, FilePerms, HostMonotonicClock, HostWallClock,
+};
+
+use crate::{
+    runtime::StoreData,
+    snapshot::persist::{read_bytes, write_bytes, Persist},
+};
+
+/// Options for WASI support.
+#[derive(Clone, Debug)]
+pub struct WasiOptions {
+    /// A directory for guest workspaces.
+    ///
+    /// Each container gets a new directory under here, named with a random ID
+    /// for the run, which is preopened as the guest's working directory. See
+    /// [`Container::workspace`][crate::runtime::Container::workspace]. Files
+    /// aren't snapshotted, or removed when the container is dropped.
+    pub workspaces: Option<PathBuf>,
+    /// The maximum number of bytes of stdout, and of stderr, to capture.
+    pub output_capacity: usize,
+}
+
+impl Default for WasiOptions {
+    fn default() -> Self {
+        Self {
+            workspaces: None,
+            output_capacity: 1024 * 1024,
+        }
+    }
+}
+
+/// The host side of a container's WASI support.
+pub(crate) struct Wasi {
+    recorder: Recorder,
+    workspace: Option<PathBuf>,
+    stdout: MemoryOutputPipe,
+    stderr: MemoryOutputPipe,
+}
+
+impl Wasi {
+    pub fn new(options: &WasiOptions) -> Result<(Self, WasiP1Ctx)> {
+        let (wasi, mut builder) = Self::builder(options)?;
+        Ok((wasi, builder.build_p1()))
+    }
+
+    /// [`Self::new`], with a WASI preview 2 context, for components.
+    pub fn new_p2(options: &WasiOptions) -> Result<(Self, WasiCtx)> {
+        let (wasi, mut builder) = Self::builder(options)?;
+        Ok((wasi, builder.build()))
+    }
+
+    fn builder(options: &WasiOptions) -> Result<(Self, WasiCtxBuilder)> {
+        let recorder = Recorder::default();
+        let stdout = MemoryOutputPipe::new(options.output_capacity);
+        let stderr = MemoryOutputPipe::new(options.output_capacity);
+        let mut builder = WasiCtxBuilder::new();
+
+        builder
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .wall_clock(recorder.clone())
+            .monotonic_clock(recorder.clone())
+            .secure_random(recorder.clone())
+            .insecure_random(recorder.clone())
+            .insecure_random_seed(0);
+
+        let workspace = match &options.workspaces {
+            Some(workspaces) => {
+                let run_id = OsRng.next_u64();
+                let workspace = workspaces.join(format!("{run_id:016x}"));
+                fs::create_dir_all(&workspace)
+                    .with_context(|| format!("Creating WASI workspace {workspace:?}"))?;
+                builder
+                    .preopened_dir(&workspace, ".", DirPerms::all(), FilePerms::all())
+                    .with_context(|| format!("Opening WASI workspace {workspace:?}"))?;
+                Some(workspace)
+            }
+            None => None,
+        };
+
+        let wasi = Self {
+            recorder,
+            workspace,
+            stdout,
+            stderr,
+        };
+
+        Ok((wasi, builder))
+    }
+
+    /// Add WASI imports to `linker`. Use the async imports for engines with
+    /// async support.
+    pub fn add_to_linker(linker: &mut Linker<StoreData>, is_async: bool) -> Result<()> {
+        fn wasi_ctx(data: &mut StoreData) -> &mut WasiP1Ctx {
+            data.wasi
+                .as_mut()
+                .expect("WASI imports should only be linked when WASI is enabled")
+        }
+
+        if is_async {
+            wasmtime_wasi::preview1::add_to_linker_async(linker, wasi_ctx)
+        } else {
+            wasmtime_wasi::preview1::add_to_linker_sync(linker, wasi_ctx)
+        }
+    }
+
+    pub fn workspace(&self) -> Option<&Path> {
+        self.workspace.as_deref()
+    }
+
+    pub fn stdout(&self) -> Vec<u8> {
+        self.stdout.contents().to_vec()
+    }
+
+    pub fn stderr(&self) -> Vec<u8> {
+        self.stderr.contents().to_vec()
+    }
+
+    pub fn snapshot(&self) -> WasiSnapshot {
+        self.recorder.0.lock().unwrap().snapshot()
+    }
+
+    pub fn restore(&self, snapshot: &WasiSnapshot) {
+        self.recorder.0.lock().unwrap().restore(snapshot)
+    }
+}
+
+/// Clock readings and random bytes a guest has seen, in the order it saw
+/// them.
+#[derive(Clone, Default, Eq, PartialEq)]
+struct Log {
+    wall_clock: Vec<Duration>,
+    monotonic_clock: Vec<u64>,
+    random: Vec<u8>,
+}
+
+impl Log {
+    /// Is `self` the first part of `other`?
+    fn is_prefix_of(&self, other: &Self) -> bool {
+        other.wall_clock.starts_with(&self.wall_clock)
+            && other.monotonic_clock.starts_with(&self.monotonic_clock)
+            && other.random.starts_with(&self.random)
+    }
+}
+
+impl Persist for Log {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.wall_clock.len().write(w)?;
+
+        for time in &self.wall_clock {
+            time.as_secs().write(w)?;
+            time.subsec_nanos().write(w)?;
+        }
+
+        self.monotonic_clock.write(w)?;
+        write_bytes(w, &self.random)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        let wall_clock_len = usize::read(r)?;
+        let mut wall_clock = Vec::new();
+
+        for _ in 0..wall_clock_len {
+            wall_clock.push(Duration::new(Persist::read(r)?, Persist::read(r)?));
+        }
+
+        Ok(Self {
+            wall_clock,
+            monotonic_clock: Persist::read(r)?,
+            random: read_bytes(r)?,
+        })
+    }
+}
+
+/// How far through each part of the [`Log`] the guest is.
+#[derive(Copy, Clone, Default)]
+struct Positions {
+    wall_clock: usize,
+    monotonic_clock: usize,
+    random: usize,
+}
+
+/// The guest's WASI state when a snapshot was taken.
+#[derive(Clone)]
+pub(crate) struct WasiSnapshot {
+    log: Log,
+}
+
+impl Persist for WasiSnapshot {
+    fn write(&self, w: &mut impl Write) -> Result<()> {
+        self.log.write(w)
+    }
+
+    fn read(r: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            log: Persist::read(r)?,
+        })
+    }
+}
+
+struct Recording {
+    log: Log,
+    positions: Positions,
+    /// Live monotonic clock readings are offset from here, so they carry on
+    /// from the last recorded reading.
+    monotonic_start: Instant,
+    monotonic_offset: u64,
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Self {
+            log: Log::default(),
+            positions: Positions::default(),
+            monotonic_start: Instant::now(),
+            monotonic_offset: 0,
+        }
+    }
+}
+
+impl Recording {
+    /// Everything the guest has seen so far.
+    fn snapshot(&self) -> WasiSnapshot {
+        let Positions {
+            wall_clock,
+            monotonic_clock,
+            random,
+        } = self.positions;
+
+        WasiSnapshot {
+            log: Log {
+                wall_clock: self.log.wall_clock[..wall_clock].to_vec(),
+                monotonic_clock: self.log.monotonic_clock[..monotonic_clock].to_vec(),
+                random: self.log.random[..random].to_vec(),
+            },
+        }
+    }
+
+    /// Rewind to `snapshot`.
+    ///
+    /// If the snapshot is from this recording, we keep what was recorded
+    /// after it, so the guest sees the same values again.
+    fn restore(&mut self, snapshot: &WasiSnapshot) {
+        if !snapshot.log.is_prefix_of(&self.log) {
+            self.log = snapshot.log.clone();
+        }
+
+        self.positions = Positions {
+            wall_clock: snapshot.log.wall_clock.len(),
+            monotonic_clock: snapshot.log.monotonic_clock.len(),
+            random: snapshot.log.random.len(),
+        };
+        self.monotonic_start = Instant::now();
+        self.monotonic_offset = self.log.monotonic_clock.last().copied().unwrap_or(0);
+    }
+
+    fn wall_clock(&mut self) -> Duration {
+        let position = &mut self.positions.wall_clock;
+
+        if let Some(&time) = self.log.wall_clock.get(*position) {
+            *position += 1;
+            return time;
+        }
+
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.log.wall_clock.push(time);
+        *position += 1;
+        time
+    }
+
+    fn monotonic_clock(&mut self) -> u64 {
+        let position = &mut self.positions.monotonic_clock;
+
+        if let Some(&time) = self.log.monotonic_clock.get(*position) {
+            *position += 1;
+            return time;
+        }
+
+        let elapsed = u64::try_from(self.monotonic_start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        let last = self.log.monotonic_clock.last().copied().unwrap_or(0);
+        let time = self.monotonic_offset.saturating_add(elapsed).max(last);
+        self.log.monotonic_clock.push(time);
+        *position += 1;
+        time
+    }
+
+    fn random(&mut self, dest: &mut [u8]) {
+        let position = self.positions.random;
+        let recorded = self
+            .log
+            .random
+            .len()
+            .saturating_sub(position)
+            .min(dest.len());
+        let (replayed, live) = dest.split_at_mut(recorded);
+        replayed.copy_from_slice(&self.log.random[position..position + recorded]);
+        OsRng.fill_bytes(live);
+        self.log.random.extend_from_slice(live);
+        self.positions.random += dest.len();
+    }
+}
+
+/// Records clock readings and random bytes, and replays them after a restore.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Recording>>);
+
+impl HostWallClock for Recorder {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> Duration {
+        self.0.lock().unwrap().wall_clock()
+    }
+}
+
+impl HostMonotonicClock for Recorder {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        self.0.lock().unwrap().monotonic_clock()
+    }
+}
+
+impl RngCore for Recorder {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.lock().unwrap().random(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
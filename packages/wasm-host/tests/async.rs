@@ -0,0 +1,116 @@
+use std::{
+    fs,
+    path::PathBuf,
+    pin::pin,
+    task::{Context, Poll},
+    thread,
+    time::{Duration, Instant},
+};
+
+use codastraea_wasm_host::{
+    limits::{LimitExceeded, Limits},
+    outcome::RunOutcome,
+    runtime::ContainerBuilder,
+};
+use futures::{executor::block_on, task::noop_waker_ref, FutureExt};
+
+const SPIN: &str = r#"
+    (module
+      (memory (export "memory") 1)
+
+      (func (export "__codastraea_register_workflows") (result i32)
+        (i32.const 0))
+
+      (func (export "__codastraea_init_workflow") (param i32))
+
+      (func (export "__codastraea_run") (result i32)
+        (loop $spin (br $spin))
+        (i32.const 0)))
+"#;
+
+#[test]
+fn run_async() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/counter.wat");
+    let bytes = fs::read(path).unwrap();
+
+    block_on(async {
+        let mut container = ContainerBuilder::new()
+            .build_from_bytes_async(&bytes)
+            .await
+            .unwrap();
+        container.register_workflows_async().await.unwrap();
+        container
+            .init_workflow_async("conformance", "counter")
+            .await
+            .unwrap();
+        assert!(container.run().is_err());
+
+        let mut checkpoints = 0;
+
+        while container.run_async().await.unwrap().is_checkpoint() {
+            checkpoints += 1;
+        }
+
+        assert_eq!(checkpoints, 2);
+    });
+}
+
+#[test]
+fn async_deadline() {
+    let deadline = Duration::from_millis(50);
+    let builder = ContainerBuilder::new().limits(Limits {
+        call_deadline: Some(deadline),
+        ..Limits::default()
+    });
+
+    block_on(async {
+        let mut container = builder
+            .build_from_bytes_async(SPIN.as_bytes())
+            .await
+            .unwrap();
+        let outcome = container.run_async().await.unwrap();
+        assert!(matches!(
+            outcome,
+            RunOutcome::LimitExceeded(LimitExceeded::Deadline { deadline: d }) if d == deadline
+        ));
+    });
+}
+
+#[test]
+fn async_deadline_counts_wall_clock_time() {
+    let deadline = Duration::from_millis(100);
+    let builder = ContainerBuilder::new().limits(Limits {
+        call_deadline: Some(deadline),
+        ..Limits::default()
+    });
+    let mut container = block_on(builder.build_from_bytes_async(SPIN.as_bytes())).unwrap();
+
+    // Poll slowly, so the guest is suspended for most of the deadline.
+    let mut run = pin!(container.run_async());
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let start = Instant::now();
+    let outcome = loop {
+        match run.poll_unpin(&mut cx) {
+            Poll::Ready(outcome) => break outcome.unwrap(),
+            Poll::Pending => thread::sleep(Duration::from_millis(50)),
+        }
+    };
+
+    assert!(matches!(
+        outcome,
+        RunOutcome::LimitExceeded(LimitExceeded::Deadline { .. })
+    ));
+    assert!(start.elapsed() < deadline * 4, "{:?}", start.elapsed());
+}
+
+#[test]
+fn sync_build_rejects_async_engine() {
+    let bytes = SPIN.as_bytes();
+    assert!(ContainerBuilder::new().build_from_bytes(bytes).is_ok());
+
+    let mut config = wasmtime::Config::new();
+    config.async_support(true);
+    let engine = wasmtime::Engine::new(&config).unwrap();
+    let builder = ContainerBuilder::new().engine(&engine);
+    assert!(builder.build_from_bytes(bytes).is_err());
+}
@@ -0,0 +1,120 @@
+//! Create containers through a [`ModuleCache`], in a fresh directory for each
+//! test.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use codastraea_wasm_host::{
+    cache::ModuleCache,
+    runtime::{Container, ContainerBuilder},
+};
+
+/// The `counter` test guest, in the binary format, in a file unique to `test`.
+fn counter_module(test: &str) -> PathBuf {
+    let wat = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/counter.wat");
+    let path = std::env::temp_dir().join(format!(
+        "codastraea-cache-{test}-{}.wasm",
+        std::process::id()
+    ));
+    fs::write(&path, wat::parse_file(wat).unwrap()).unwrap();
+    path
+}
+
+/// An empty cache directory, unique to `test`.
+fn cache_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("codastraea-cache-{test}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// The names of the files in `dir`, sorted.
+fn entries(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+fn with_cache(module: &Path, cache: &ModuleCache) -> anyhow::Result<Container> {
+    ContainerBuilder::new()
+        .cache(cache.clone())
+        .build_from_file(module)
+}
+
+#[test]
+fn cache_hit() {
+    let dir = cache_dir("hit");
+    let cache = ModuleCache::new(&dir).unwrap();
+    let module = counter_module("hit");
+
+    let compiled = with_cache(&module, &cache).unwrap();
+    let [entry] = entries(&dir).try_into().unwrap();
+    assert!(entry.ends_with(".module"));
+    let modified = fs::metadata(dir.join(&entry)).unwrap().modified().unwrap();
+
+    let mut container = with_cache(&module, &cache).unwrap();
+    assert_eq!(container.fingerprint(), compiled.fingerprint());
+    assert_eq!(entries(&dir), [entry.as_str()]);
+    assert_eq!(
+        fs::metadata(dir.join(&entry)).unwrap().modified().unwrap(),
+        modified,
+        "A cache hit shouldn't rewrite the entry"
+    );
+
+    container.register_workflows().unwrap();
+    container.init_workflow("conformance", "counter").unwrap();
+
+    while container.run().unwrap().is_checkpoint() {}
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn corrupt_entry() {
+    let dir = cache_dir("corrupt");
+    let cache = ModuleCache::new(&dir).unwrap();
+    let module = counter_module("corrupt");
+
+    let compiled = with_cache(&module, &cache).unwrap();
+    let [entry] = entries(&dir).try_into().unwrap();
+    fs::write(dir.join(&entry), b"corrupt").unwrap();
+
+    // The corrupt entry is ignored and replaced.
+    let recompiled = with_cache(&module, &cache).unwrap();
+    assert_eq!(recompiled.fingerprint(), compiled.fingerprint());
+    assert_eq!(entries(&dir), [entry.as_str()]);
+    assert_ne!(fs::read(dir.join(&entry)).unwrap(), b"corrupt");
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn failed_write_leaves_no_temp_file() {
+    let module = counter_module("failed-write");
+
+    let entry = {
+        let dir = cache_dir("entry-name");
+        with_cache(&module, &ModuleCache::new(&dir).unwrap()).unwrap();
+        let [entry] = entries(&dir).try_into().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        entry
+    };
+
+    // A non-empty directory in place of the entry makes the rename fail.
+    let dir = cache_dir("failed-write");
+    let cache = ModuleCache::new(&dir).unwrap();
+    fs::create_dir(dir.join(&entry)).unwrap();
+    fs::write(dir.join(&entry).join("file"), b"").unwrap();
+
+    let error = with_cache(&module, &cache).err().unwrap();
+    assert!(
+        format!("{error:#}").contains("Writing cache entry"),
+        "{error:#}"
+    );
+    assert_eq!(entries(&dir), [entry]);
+
+    fs::remove_dir_all(dir).unwrap();
+}
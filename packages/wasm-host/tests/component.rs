@@ -0,0 +1,79 @@
+//! Build `codastraea-test-workflow` as a component, and run it to completion.
+//!
+//! This needs the `wasm32-wasip2` target, so it's ignored by default. Run it
+//! with `cargo test --test component -- --ignored`.
+use std::{path::PathBuf, process::Command};
+
+use codastraea_server_api::{CallTreeNodeId, NodeStatus, NodeVecDiff};
+use codastraea_wasm_host::{outcome::RunOutcome, runtime::Container};
+use futures::{FutureExt, StreamExt};
+
+const TARGET: &str = "wasm32-wasip2";
+
+/// Build the test workflow component.
+fn build_component() -> PathBuf {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let sysroot = Command::new("rustc")
+        .args(["--print", "sysroot"])
+        .output()
+        .unwrap();
+    let sysroot = PathBuf::from(String::from_utf8(sysroot.stdout).unwrap().trim());
+
+    assert!(
+        sysroot.join("lib/rustlib").join(TARGET).exists(),
+        "The `{TARGET}` target isn't installed. Install it with `rustup target add {TARGET}`."
+    );
+
+    // A separate target dir, as the workspace's is locked while tests run.
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("component");
+    let status = Command::new(cargo)
+        .args([
+            "build",
+            "--release",
+            "-p",
+            "codastraea-test-workflow",
+            "--target",
+            TARGET,
+            "--features",
+            "codastraea-wasm-guest/component",
+            "--target-dir",
+        ])
+        .arg(&target_dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    target_dir
+        .join(TARGET)
+        .join("release/codastraea_test_workflow.wasm")
+}
+
+#[test]
+#[ignore = "needs the wasm32-wasip2 target"]
+fn test_workflow() {
+    let mut container = Container::from_file(&build_component()).unwrap();
+    let node_store = container.node_store();
+    container.register_workflows().unwrap();
+    container
+        .init_workflow("codastraea_test_workflow", "counter")
+        .unwrap();
+
+    for i in 0..10 {
+        let RunOutcome::Checkpoint { label } = container.run().unwrap() else {
+            panic!("Expected a checkpoint");
+        };
+        assert_eq!(label, Some(format!("iteration {i}")));
+    }
+
+    let RunOutcome::Completed { return_value } = container.run().unwrap() else {
+        panic!("Expected completion");
+    };
+    assert_eq!(return_value.as_deref(), Some("10"));
+
+    let Some(Some(NodeVecDiff::Replace(nodes))) =
+        node_store.watch(CallTreeNodeId::Root).next().now_or_never()
+    else {
+        panic!("Expected a root node");
+    };
+    assert_eq!(nodes[0].status, NodeStatus::Complete);
+}
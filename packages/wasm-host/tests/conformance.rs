@@ -0,0 +1,305 @@
+//! Run the hand-written guests in `tests/conformance` against [`Container`],
+//! to check the host implements the ABI described in `ABI.md`, and the WIT
+//! interface in `wit/codastraea.wit`.
+use std::path::PathBuf;
+
+use codastraea_server_api::{CallTreeNodeId, NodeVecDiff};
+use codastraea_wasm_host::{outcome::RunOutcome, runtime::Container, thread::NodeStore};
+use futures::{FutureExt, StreamExt};
+use wasmtime::Trap;
+
+fn guest_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/conformance")
+        .join(format!("{name}.wat"))
+}
+
+fn container(name: &str) -> Container {
+    Container::from_file(&guest_path(name)).unwrap()
+}
+
+fn load_error(name: &str) -> String {
+    match Container::from_file(&guest_path(name)) {
+        Ok(_) => panic!("Expected '{name}' to fail to load"),
+        Err(e) => format!("{e:#}"),
+    }
+}
+
+/// Run until the workflow is complete, and return the number of checkpoints.
+fn run_to_completion(container: &mut Container) -> usize {
+    let mut checkpoints = 0;
+
+    while container.run().unwrap().is_checkpoint() {
+        checkpoints += 1;
+    }
+
+    checkpoints
+}
+
+/// The call tree, with one line per node, indented by depth.
+fn call_tree(node_store: &NodeStore) -> Vec<String> {
+    let mut lines = Vec::new();
+    add_nodes(node_store, CallTreeNodeId::Root, 0, &mut lines);
+    lines
+}
+
+fn add_nodes(node_store: &NodeStore, id: CallTreeNodeId, depth: usize, lines: &mut Vec<String>) {
+    // Watching a non-empty node vec sends its current contents straight away.
+    let Some(Some(NodeVecDiff::Replace(nodes))) = node_store.watch(id).next().now_or_never() else {
+        return;
+    };
+
+    for node in nodes {
+        lines.push(format!(
+            "{}{}: {:?}",
+            "  ".repeat(depth),
+            node.typ.as_snake_str(),
+            node.status
+        ));
+        add_nodes(node_store, CallTreeNodeId::Child(node.id), depth + 1, lines);
+    }
+}
+
+#[test]
+fn checkpoints() {
+    let mut container = container("counter");
+    container.register_workflows().unwrap();
+    container.init_workflow("conformance", "counter").unwrap();
+    assert_eq!(run_to_completion(&mut container), 2);
+
+    // Re-initializing starts the workflow again.
+    container.init_workflow("conformance", "counter").unwrap();
+    assert_eq!(run_to_completion(&mut container), 2);
+}
+
+#[test]
+fn unknown_workflow() {
+    let mut container = container("counter");
+    container.register_workflows().unwrap();
+    let error = container
+        .init_workflow("conformance", "missing")
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Unknown workflow conformance::missing");
+}
+
+#[test]
+fn restore_at_checkpoint() {
+    let mut container = container("counter");
+    container.register_workflows().unwrap();
+    container.init_workflow("conformance", "counter").unwrap();
+    assert!(container.run().unwrap().is_checkpoint());
+    let snapshot = container.snapshot().unwrap();
+    assert_eq!(run_to_completion(&mut container), 1);
+
+    container.restore(&snapshot).unwrap();
+    assert_eq!(run_to_completion(&mut container), 1);
+}
+
+#[test]
+fn trace() {
+    let mut container = container("trace");
+    container.register_workflows().unwrap();
+    container.init_workflow("conformance", "traced").unwrap();
+    let node_store = container.node_store();
+
+    assert!(container.run().unwrap().is_checkpoint());
+    assert_eq!(
+        call_tree(&node_store),
+        ["traced: Running", "  if: Running", "    condition: Running"]
+    );
+
+    assert!(!container.run().unwrap().is_checkpoint());
+    assert_eq!(
+        call_tree(&node_store),
+        [
+            "traced: Complete",
+            "  if: Complete",
+            "    condition: Complete",
+            "    then: Complete",
+            "  else_if: Complete",
+            "  else: Complete",
+        ]
+    );
+}
+
+#[test]
+fn restore_trace() {
+    let mut container = container("trace");
+    container.register_workflows().unwrap();
+    container.init_workflow("conformance", "traced").unwrap();
+    let node_store = container.node_store();
+    assert!(container.run().unwrap().is_checkpoint());
+    let running = call_tree(&node_store);
+    let snapshot = container.snapshot().unwrap();
+    assert!(!container.run().unwrap().is_checkpoint());
+
+    container.restore(&snapshot).unwrap();
+    assert_eq!(call_tree(&node_store), running);
+    assert!(!container.run().unwrap().is_checkpoint());
+    assert_eq!(call_tree(&node_store)[0], "traced: Complete");
+}
+
+#[test]
+fn component() {
+    let mut container = container("component");
+    container.register_workflows().unwrap();
+    container.init_workflow("conformance", "component").unwrap();
+    let node_store = container.node_store();
+
+    let RunOutcome::Checkpoint { label } = container.run().unwrap() else {
+        panic!("Expected a checkpoint");
+    };
+    assert_eq!(label.as_deref(), Some("halfway"));
+    assert_eq!(
+        call_tree(&node_store),
+        ["component: Running", "  if: Running"]
+    );
+
+    let RunOutcome::Completed { return_value } = container.run().unwrap() else {
+        panic!("Expected completion");
+    };
+    assert_eq!(return_value.as_deref(), Some("42"));
+    assert_eq!(
+        call_tree(&node_store),
+        ["component: Complete", "  if: Complete"]
+    );
+
+    // Re-initializing starts the workflow again.
+    container.init_workflow("conformance", "component").unwrap();
+    assert_eq!(run_to_completion(&mut container), 1);
+}
+
+#[test]
+fn reported_outcome() {
+    let mut container = container("outcome");
+    container.register_workflows().unwrap();
+    container.init_workflow("conformance", "reported").unwrap();
+
+    let RunOutcome::Checkpoint { label } = container.run().unwrap() else {
+        panic!("Expected a checkpoint");
+    };
+    assert_eq!(label.as_deref(), Some("halfway"));
+
+    let RunOutcome::Completed { return_value } = container.run().unwrap() else {
+        panic!("Expected completion");
+    };
+    assert_eq!(return_value.as_deref(), Some("42"));
+}
+
+#[test]
+fn trapped_outcome() {
+    let mut container = container("outcome");
+    container.register_workflows().unwrap();
+    container.init_workflow("conformance", "trapped").unwrap();
+
+    let node_store = container.node_store();
+
+    let RunOutcome::Trapped {
+        trap,
+        backtrace,
+        panic: None,
+    } = container.run().unwrap()
+    else {
+        panic!("Expected a trap");
+    };
+    assert_eq!(trap, Trap::UnreachableCodeReached);
+    let frames = backtrace.expect("Expected a backtrace");
+    let names: Vec<_> = frames
+        .frames()
+        .iter()
+        .map(|frame| frame.func_name().unwrap_or("?"))
+        .collect();
+    assert_eq!(names[0], "trap");
+
+    // Nodes that were running when the guest trapped are marked as failed.
+    assert_eq!(call_tree(&node_store), ["trapped: Failed", "  if: Failed"]);
+    let Some(Some(NodeVecDiff::Replace(nodes))) =
+        node_store.watch(CallTreeNodeId::Root).next().now_or_never()
+    else {
+        panic!("Expected a root node");
+    };
+    assert_eq!(
+        nodes[0].error.as_deref(),
+        Some("Guest trapped: wasm trap: wasm `unreachable` instruction executed")
+    );
+}
+
+#[test]
+fn panicked_outcome() {
+    let mut container = container("outcome");
+    container.register_workflows().unwrap();
+    container.init_workflow("conformance", "panicked").unwrap();
+    let node_store = container.node_store();
+
+    let RunOutcome::Trapped { panic, .. } = container.run().unwrap() else {
+        panic!("Expected a trap");
+    };
+    assert_eq!(panic.as_deref(), Some("oh no"));
+
+    let Some(Some(NodeVecDiff::Replace(nodes))) =
+        node_store.watch(CallTreeNodeId::Root).next().now_or_never()
+    else {
+        panic!("Expected a root node");
+    };
+    assert_eq!(nodes[0].error.as_deref(), Some("Guest panicked: oh no"));
+}
+
+#[test]
+fn unmatched_end() {
+    let mut container = container("outcome");
+    container.register_workflows().unwrap();
+    container.init_workflow("conformance", "unmatched").unwrap();
+    let node_store = container.node_store();
+
+    // The host reports the error, rather than panicking.
+    let error = format!("{:#}", container.run().unwrap_err());
+    assert!(
+        error.contains("Guest ended 'if', but 'unmatched' is running"),
+        "{error}"
+    );
+    assert_eq!(call_tree(&node_store), ["unmatched: Failed"]);
+}
+
+#[test]
+fn legacy_guest() {
+    let mut container = container("legacy");
+    container.register_workflows().unwrap();
+    assert!(!container.run().unwrap().is_checkpoint());
+}
+
+#[test]
+fn unsupported_version() {
+    assert!(load_error("unsupported_version").contains("Guest ABI version 1000 isn't supported"));
+}
+
+#[test]
+fn newer_version() {
+    let error = load_error("newer_version");
+    assert!(
+        error.contains("Guest ABI version 1000 isn't supported"),
+        "{error}"
+    );
+    assert!(!error.contains("isn't provided by the host"), "{error}");
+}
+
+#[test]
+fn mismatched_version() {
+    assert!(load_error("mismatched_version").contains(
+        "Guest declares ABI version 1 in custom section 'codastraea_abi_version', but \
+         '__codastraea_abi_version' returns 2"
+    ));
+}
+
+#[test]
+fn invalid_guest() {
+    let error = load_error("invalid");
+
+    for problem in [
+        "Import '__codastraea_unknown' isn't provided by the host",
+        "Import '__codastraea_log' has the wrong signature",
+        "Export '__codastraea_init_workflow' has the wrong signature",
+        "Export '__codastraea_run' is missing",
+    ] {
+        assert!(error.contains(problem), "'{problem}' not in:\n{error}");
+    }
+}
@@ -0,0 +1,108 @@
+;; A component with one workflow, `component`, that stops at a labelled
+;; checkpoint inside a traced `if`, then reports a return value.
+;;
+;; Exercises the WIT interface in `wit/codastraea.wit`, and WASI preview 2,
+;; which the host links for every component.
+(component
+  (import "codastraea:workflow/host" (instance $host
+    (type $node_kind (enum "if" "condition" "then" "else-if" "else"))
+    (export $node_kind_export "node-kind" (type (eq $node_kind)))
+    (export "log" (func (param "message" string)))
+    (export "register-workflow-index"
+      (func (param "module" string) (param "name" string) (param "index" u32)))
+    (export "fn-begin" (func (param "module" string) (param "name" string)))
+    (export "fn-end" (func (param "module" string) (param "name" string)))
+    (export "checkpoint-label" (func (param "label" string)))
+    (export "return-value" (func (param "value" string)))
+    (export "begin" (func (param "node" $node_kind_export)))
+    (export "end" (func (param "node" $node_kind_export)))))
+  (import "wasi:random/random@0.2.0" (instance $random
+    (export "get-random-u64" (func (result u64)))))
+
+  (core module $Memory
+    (memory (export "memory") 1))
+  (core instance $memory (instantiate $Memory))
+  (alias core export $memory "memory" (core memory $mem))
+
+  (core func $log (canon lower (func $host "log") (memory $mem)))
+  (core func $register_workflow_index
+    (canon lower (func $host "register-workflow-index") (memory $mem)))
+  (core func $fn_begin (canon lower (func $host "fn-begin") (memory $mem)))
+  (core func $fn_end (canon lower (func $host "fn-end") (memory $mem)))
+  (core func $checkpoint_label (canon lower (func $host "checkpoint-label") (memory $mem)))
+  (core func $return_value (canon lower (func $host "return-value") (memory $mem)))
+  (core func $begin (canon lower (func $host "begin")))
+  (core func $end (canon lower (func $host "end")))
+  (core func $get_random_u64 (canon lower (func $random "get-random-u64")))
+
+  (core module $Guest
+    (import "host" "log" (func $log (param i32 i32)))
+    (import "host" "register-workflow-index"
+      (func $register_workflow_index (param i32 i32 i32 i32 i32)))
+    (import "host" "fn-begin" (func $fn_begin (param i32 i32 i32 i32)))
+    (import "host" "fn-end" (func $fn_end (param i32 i32 i32 i32)))
+    (import "host" "checkpoint-label" (func $checkpoint_label (param i32 i32)))
+    (import "host" "return-value" (func $return_value (param i32 i32)))
+    (import "host" "begin" (func $begin (param i32)))
+    (import "host" "end" (func $end (param i32)))
+    (import "random" "get-random-u64" (func $get_random_u64 (result i64)))
+    (import "env" "memory" (memory 1))
+
+    (global $runs (mut i32) (i32.const 0))
+
+    (data (i32.const 16) "conformance")
+    (data (i32.const 32) "component")
+    (data (i32.const 48) "halfway")
+    (data (i32.const 64) "42")
+
+    (func (export "abi-version") (result i32)
+      (i32.const 3))
+
+    (func (export "register-workflows") (result i32)
+      (call $register_workflow_index
+        (i32.const 16) (i32.const 11)
+        (i32.const 32) (i32.const 9)
+        (i32.const 0))
+      (i32.const 1))
+
+    (func (export "init-workflow") (param $index i32)
+      (global.set $runs (i32.const 0)))
+
+    (func (export "run") (result i32)
+      (global.set $runs (i32.add (global.get $runs) (i32.const 1)))
+
+      (if (i32.eq (global.get $runs) (i32.const 1))
+        (then
+          (call $fn_begin (i32.const 16) (i32.const 11) (i32.const 32) (i32.const 9))
+          (drop (call $get_random_u64))
+          ;; `node-kind.if`
+          (call $begin (i32.const 0))
+          (call $checkpoint_label (i32.const 48) (i32.const 7))
+          (return (i32.const 1))))
+
+      (call $end (i32.const 0))
+      (call $fn_end (i32.const 16) (i32.const 11) (i32.const 32) (i32.const 9))
+      (call $return_value (i32.const 64) (i32.const 2))
+      (i32.const 0)))
+
+  (core instance $guest (instantiate $Guest
+    (with "host" (instance
+      (export "log" (func $log))
+      (export "register-workflow-index" (func $register_workflow_index))
+      (export "fn-begin" (func $fn_begin))
+      (export "fn-end" (func $fn_end))
+      (export "checkpoint-label" (func $checkpoint_label))
+      (export "return-value" (func $return_value))
+      (export "begin" (func $begin))
+      (export "end" (func $end))))
+    (with "random" (instance (export "get-random-u64" (func $get_random_u64))))
+    (with "env" (instance $memory))))
+
+  (func (export "abi-version") (result u32)
+    (canon lift (core func $guest "abi-version")))
+  (func (export "register-workflows") (result u32)
+    (canon lift (core func $guest "register-workflows")))
+  (func (export "init-workflow") (param "index" u32)
+    (canon lift (core func $guest "init-workflow")))
+  (func (export "run") (result bool)
+    (canon lift (core func $guest "run"))))
@@ -0,0 +1,40 @@
+;; A workflow that stops at 2 checkpoints, then completes.
+;;
+;; Exercises `__codastraea_log`, `__codastraea_register_workflow_index` and the
+;; checkpoint semantics of `__codastraea_run`, and declares its ABI version in a
+;; custom section.
+(module
+  (@custom "codastraea_abi_version" "\02\00\00\00")
+
+  (import "codastraea" "__codastraea_log" (func $log (param i32 i32)))
+  (import "codastraea" "__codastraea_register_workflow_index"
+    (func $register_workflow_index (param i32 i32 i32 i32 i32)))
+
+  (memory (export "memory") 1)
+
+  ;; The number of calls to `__codastraea_run` since the workflow started.
+  (global $runs (mut i32) (i32.const 0))
+
+  (data (i32.const 16) "conformance")
+  (data (i32.const 32) "counter")
+  (data (i32.const 48) "Registering workflows")
+
+  (func (export "__codastraea_abi_version") (result i32)
+    (i32.const 2))
+
+  (func (export "__codastraea_register_workflows") (result i32)
+    (call $log (i32.const 48) (i32.const 21))
+    (call $register_workflow_index
+      (i32.const 16) (i32.const 11)
+      (i32.const 32) (i32.const 7)
+      (i32.const 0))
+    (i32.const 1))
+
+  (func (export "__codastraea_init_workflow") (param $index i32)
+    (if (local.get $index) (then (unreachable)))
+    (global.set $runs (i32.const 0)))
+
+  ;; Returns 1 at a checkpoint, or 0 when the workflow is complete.
+  (func (export "__codastraea_run") (result i32)
+    (global.set $runs (i32.add (global.get $runs) (i32.const 1)))
+    (i32.lt_u (global.get $runs) (i32.const 3))))
@@ -0,0 +1,17 @@
+;; A guest that doesn't match the ABI.
+(module
+  ;; The host doesn't provide this.
+  (import "codastraea" "__codastraea_unknown" (func))
+  ;; Wrong signature.
+  (import "codastraea" "__codastraea_log" (func (param i32)))
+
+  (memory (export "memory") 1)
+
+  (func (export "__codastraea_register_workflows") (result i32)
+    (i32.const 0))
+
+  ;; Wrong signature.
+  (func (export "__codastraea_init_workflow"))
+
+  ;; `__codastraea_run` is missing.
+)
@@ -0,0 +1,17 @@
+;; A guest built before ABI version 2. It has no `__codastraea_abi_version`
+;; export, and imports from `env`.
+(module
+  (import "env" "__codastraea_log" (func $log (param i32 i32)))
+
+  (memory (export "memory") 1)
+
+  (data (i32.const 16) "Registering workflows")
+
+  (func (export "__codastraea_register_workflows") (result i32)
+    (call $log (i32.const 16) (i32.const 21))
+    (i32.const 0))
+
+  (func (export "__codastraea_init_workflow") (param i32))
+
+  (func (export "__codastraea_run") (result i32)
+    (i32.const 0)))
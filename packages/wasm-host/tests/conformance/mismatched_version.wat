@@ -0,0 +1,17 @@
+;; A guest whose custom section declares a different ABI version to the one
+;; `__codastraea_abi_version` returns.
+(module
+  (@custom "codastraea_abi_version" "\01\00\00\00")
+
+  (memory (export "memory") 1)
+
+  (func (export "__codastraea_abi_version") (result i32)
+    (i32.const 2))
+
+  (func (export "__codastraea_register_workflows") (result i32)
+    (i32.const 0))
+
+  (func (export "__codastraea_init_workflow") (param i32))
+
+  (func (export "__codastraea_run") (result i32)
+    (i32.const 0)))
@@ -0,0 +1,19 @@
+;; A guest built for a newer ABI, which imports a function this host doesn't
+;; provide. The host should report the version, not the import.
+(module
+  (@custom "codastraea_abi_version" "\e8\03\00\00")
+
+  (import "codastraea" "__codastraea_from_the_future" (func))
+
+  (memory (export "memory") 1)
+
+  (func (export "__codastraea_abi_version") (result i32)
+    (i32.const 1000))
+
+  (func (export "__codastraea_register_workflows") (result i32)
+    (i32.const 0))
+
+  (func (export "__codastraea_init_workflow") (param i32))
+
+  (func (export "__codastraea_run") (result i32)
+    (i32.const 0)))
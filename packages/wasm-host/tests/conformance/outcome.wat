@@ -0,0 +1,92 @@
+;; Four workflows: `reported`, which labels its checkpoint and reports a return
+;; value, `trapped`, which traps inside a traced `if`, `panicked`, which
+;; reports a panic message before it traps, and `unmatched`, which ends an `if`
+;; it never began.
+;;
+;; Exercises `__codastraea_checkpoint_label` and `__codastraea_return_value`,
+;; from ABI version 3, `__codastraea_panic`, from ABI version 4, and how the
+;; host reports traps and errors.
+(module
+  (import "codastraea" "__codastraea_register_workflow_index"
+    (func $register_workflow_index (param i32 i32 i32 i32 i32)))
+  (import "codastraea" "__codastraea_checkpoint_label"
+    (func $checkpoint_label (param i32 i32)))
+  (import "codastraea" "__codastraea_return_value"
+    (func $return_value (param i32 i32)))
+  (import "codastraea" "__codastraea_fn_begin" (func $fn_begin (param i32 i32 i32 i32)))
+  (import "codastraea" "__codastraea_panic" (func $panic (param i32 i32)))
+  (import "codastraea" "__codastraea_begin_if" (func $begin_if))
+  (import "codastraea" "__codastraea_end_if" (func $end_if))
+
+  (memory (export "memory") 1)
+
+  (global $workflow (mut i32) (i32.const 0))
+  (global $runs (mut i32) (i32.const 0))
+
+  (data (i32.const 16) "conformance")
+  (data (i32.const 32) "reported")
+  (data (i32.const 48) "trapped")
+  (data (i32.const 64) "halfway")
+  (data (i32.const 80) "42")
+  (data (i32.const 96) "panicked")
+  (data (i32.const 112) "oh no")
+  (data (i32.const 128) "unmatched")
+
+  (func (export "__codastraea_abi_version") (result i32)
+    (i32.const 4))
+
+  (func (export "__codastraea_register_workflows") (result i32)
+    (call $register_workflow_index
+      (i32.const 16) (i32.const 11)
+      (i32.const 32) (i32.const 8)
+      (i32.const 0))
+    (call $register_workflow_index
+      (i32.const 16) (i32.const 11)
+      (i32.const 48) (i32.const 7)
+      (i32.const 1))
+    (call $register_workflow_index
+      (i32.const 16) (i32.const 11)
+      (i32.const 96) (i32.const 8)
+      (i32.const 2))
+    (call $register_workflow_index
+      (i32.const 16) (i32.const 11)
+      (i32.const 128) (i32.const 9)
+      (i32.const 3))
+    (i32.const 4))
+
+  (func (export "__codastraea_init_workflow") (param $index i32)
+    (global.set $workflow (local.get $index))
+    (global.set $runs (i32.const 0)))
+
+  ;; Enter `trapped`, then an `if`, and trap before either ends.
+  (func $trap
+    (call $fn_begin (i32.const 16) (i32.const 11) (i32.const 48) (i32.const 7))
+    (call $begin_if)
+    (unreachable))
+
+  ;; Enter `panicked`, and report a panic before trapping.
+  (func $panic_and_trap
+    (call $fn_begin (i32.const 16) (i32.const 11) (i32.const 96) (i32.const 8))
+    (call $panic (i32.const 112) (i32.const 5))
+    (unreachable))
+
+  ;; Enter `unmatched`, then end an `if` that isn't running.
+  (func $unmatched
+    (call $fn_begin (i32.const 16) (i32.const 11) (i32.const 128) (i32.const 9))
+    (call $end_if))
+
+  ;; `reported` stops at a checkpoint labelled "halfway", then completes,
+  ;; returning "42".
+  (func (export "__codastraea_run") (result i32)
+    (if (i32.eq (global.get $workflow) (i32.const 1)) (then (call $trap)))
+    (if (i32.eq (global.get $workflow) (i32.const 2)) (then (call $panic_and_trap)))
+    (if (i32.eq (global.get $workflow) (i32.const 3)) (then (call $unmatched)))
+    (global.set $runs (i32.add (global.get $runs) (i32.const 1)))
+
+    (if (result i32) (i32.eq (global.get $runs) (i32.const 1))
+      (then
+        (call $checkpoint_label (i32.const 64) (i32.const 7))
+        (i32.const 1))
+      (else
+        (call $return_value (i32.const 80) (i32.const 2))
+        (i32.const 0)))))
@@ -0,0 +1,60 @@
+;; A workflow that calls every tracing import, with a checkpoint inside an
+;; `if` condition.
+(module
+  (import "codastraea" "__codastraea_register_workflow_index"
+    (func $register_workflow_index (param i32 i32 i32 i32 i32)))
+  (import "codastraea" "__codastraea_fn_begin" (func $fn_begin (param i32 i32 i32 i32)))
+  (import "codastraea" "__codastraea_fn_end" (func $fn_end (param i32 i32 i32 i32)))
+  (import "codastraea" "__codastraea_begin_if" (func $begin_if))
+  (import "codastraea" "__codastraea_end_if" (func $end_if))
+  (import "codastraea" "__codastraea_begin_condition" (func $begin_condition))
+  (import "codastraea" "__codastraea_end_condition" (func $end_condition))
+  (import "codastraea" "__codastraea_begin_then" (func $begin_then))
+  (import "codastraea" "__codastraea_end_then" (func $end_then))
+  (import "codastraea" "__codastraea_begin_else_if" (func $begin_else_if))
+  (import "codastraea" "__codastraea_end_else_if" (func $end_else_if))
+  (import "codastraea" "__codastraea_begin_else" (func $begin_else))
+  (import "codastraea" "__codastraea_end_else" (func $end_else))
+
+  (memory (export "memory") 1)
+
+  ;; The number of calls to `__codastraea_run` since the workflow started.
+  (global $runs (mut i32) (i32.const 0))
+
+  (data (i32.const 16) "conformance")
+  (data (i32.const 32) "traced")
+
+  (func (export "__codastraea_abi_version") (result i32)
+    (i32.const 2))
+
+  (func (export "__codastraea_register_workflows") (result i32)
+    (call $register_workflow_index
+      (i32.const 16) (i32.const 11)
+      (i32.const 32) (i32.const 6)
+      (i32.const 0))
+    (i32.const 1))
+
+  (func (export "__codastraea_init_workflow") (param $index i32)
+    (if (local.get $index) (then (unreachable)))
+    (global.set $runs (i32.const 0)))
+
+  (func (export "__codastraea_run") (result i32)
+    (global.set $runs (i32.add (global.get $runs) (i32.const 1)))
+
+    (if (i32.eq (global.get $runs) (i32.const 1))
+      (then
+        (call $fn_begin (i32.const 16) (i32.const 11) (i32.const 32) (i32.const 6))
+        (call $begin_if)
+        (call $begin_condition)
+        (return (i32.const 1))))
+
+    (call $end_condition)
+    (call $begin_then)
+    (call $end_then)
+    (call $end_if)
+    (call $begin_else_if)
+    (call $end_else_if)
+    (call $begin_else)
+    (call $end_else)
+    (call $fn_end (i32.const 16) (i32.const 11) (i32.const 32) (i32.const 6))
+    (i32.const 0)))
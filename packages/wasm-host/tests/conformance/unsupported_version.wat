@@ -0,0 +1,14 @@
+;; A guest built with an ABI version the host doesn't support.
+(module
+  (memory (export "memory") 1)
+
+  (func (export "__codastraea_abi_version") (result i32)
+    (i32.const 1000))
+
+  (func (export "__codastraea_register_workflows") (result i32)
+    (i32.const 0))
+
+  (func (export "__codastraea_init_workflow") (param i32))
+
+  (func (export "__codastraea_run") (result i32)
+    (i32.const 0)))
@@ -0,0 +1,72 @@
+use codastraea_wasm_host::instrument::instrument;
+use wasmtime::{Engine, Module};
+
+/// The exported names of `wat`, after instrumenting it.
+fn instrumented_exports(wat: &str) -> (Vec<String>, Vec<String>) {
+    let instrumented = instrument(&wat::parse_str(wat).unwrap()).unwrap();
+    let module = Module::new(&Engine::default(), &instrumented.module).unwrap();
+    let mut exports: Vec<_> = module
+        .exports()
+        .map(|export| export.name().to_string())
+        .collect();
+    exports.sort();
+    (exports, instrumented.unstable_names)
+}
+
+#[test]
+fn stable_names() {
+    let build = |extra: &str| {
+        format!(
+            r#"
+            (module
+              {extra}
+              (global $runs (mut i32) (i32.const 0))
+              (func $step (global.set $runs (i32.const 1)))
+              (func (export "run") (call $step)))
+            "#
+        )
+    };
+
+    let (exports, unstable_names) = instrumented_exports(&build(""));
+    assert_eq!(
+        exports,
+        [
+            "__codastraea_function_name_step",
+            "__codastraea_global_name_runs",
+            "run"
+        ]
+    );
+    assert!(unstable_names.is_empty());
+
+    // Adding items doesn't change the names of existing ones.
+    let (rebuilt, _) = instrumented_exports(&build(
+        "(global $extra (mut i32) (i32.const 0)) (func $unused)",
+    ));
+    assert!(exports.iter().all(|name| rebuilt.contains(name)));
+}
+
+#[test]
+fn unstable_names() {
+    let (exports, unstable_names) = instrumented_exports(
+        r#"
+        (module
+          (global (mut i32) (i32.const 0))
+          (global (@name "shared") (mut i32) (i32.const 0))
+          (global (@name "shared") (mut i32) (i32.const 0))
+          (memory 1)
+          (func (export "run")))
+        "#,
+    );
+
+    // Unnamed and duplicate names fall back to the index.
+    assert_eq!(
+        unstable_names,
+        [
+            "__codastraea_global_0",
+            "__codastraea_global_1",
+            "__codastraea_global_2",
+            "__codastraea_memory_0"
+        ]
+    );
+    assert!(unstable_names.iter().all(|name| exports.contains(name)));
+}
@@ -0,0 +1,215 @@
+//! Run guests that exceed each of their [`Limits`].
+use std::time::{Duration, Instant};
+
+use codastraea_wasm_host::{
+    cache::CompiledModule,
+    limits::{EpochTicker, LimitExceeded, Limits},
+    outcome::RunOutcome,
+    runtime::{Container, ContainerBuilder},
+};
+use wasmtime::{Config, Engine};
+
+/// Workflows that spin forever, or grow their memory or table.
+///
+/// Each one runs in a traced function, `limits::run`.
+const GUEST: &str = r#"
+    (module
+      (import "codastraea" "__codastraea_register_workflow_index"
+        (func $register_workflow_index (param i32 i32 i32 i32 i32)))
+      (import "codastraea" "__codastraea_fn_begin"
+        (func $fn_begin (param i32 i32 i32 i32)))
+
+      (memory (export "memory") 1)
+      (table 1 funcref)
+      (global $workflow (mut i32) (i32.const 0))
+
+      (data (i32.const 0) "limitsspingrow_memorygrow_tablerun")
+
+      (func (export "__codastraea_register_workflows") (result i32)
+        (call $register_workflow_index (i32.const 0) (i32.const 6) (i32.const 6) (i32.const 4)
+          (i32.const 0))
+        (call $register_workflow_index (i32.const 0) (i32.const 6) (i32.const 10) (i32.const 11)
+          (i32.const 1))
+        (call $register_workflow_index (i32.const 0) (i32.const 6) (i32.const 21) (i32.const 10)
+          (i32.const 2))
+        (i32.const 3))
+
+      (func (export "__codastraea_init_workflow") (param $index i32)
+        (global.set $workflow (local.get $index)))
+
+      (func (export "__codastraea_run") (result i32)
+        (call $fn_begin (i32.const 0) (i32.const 6) (i32.const 31) (i32.const 3))
+
+        (block $grow_table
+          (block $grow_memory
+            (block $spin
+              (br_table $spin $grow_memory $grow_table (global.get $workflow)))
+            (loop $spin (br $spin)))
+          (drop (memory.grow (i32.const 10)))
+          (return (i32.const 0)))
+        (drop (table.grow (ref.null func) (i32.const 10)))
+        (i32.const 0)))
+"#;
+
+fn container(builder: &ContainerBuilder, workflow: &str) -> Container {
+    let mut container = builder.build_from_bytes(GUEST.as_bytes()).unwrap();
+    container.register_workflows().unwrap();
+    container.init_workflow("limits", workflow).unwrap();
+    container
+}
+
+fn limits(limits: Limits) -> ContainerBuilder {
+    ContainerBuilder::new().limits(limits)
+}
+
+/// An engine configured for `limits`.
+fn engine(limits: &Limits) -> Engine {
+    let mut config = Config::new();
+    limits.configure(&mut config);
+    Engine::new(&config).unwrap()
+}
+
+/// Run `workflow`, and check it exceeds `expected`.
+fn exceeds(builder: &ContainerBuilder, workflow: &str, expected: LimitExceeded) {
+    let mut container = container(builder, workflow);
+
+    match container.run().unwrap() {
+        RunOutcome::LimitExceeded(limit) => assert_eq!(limit, expected),
+        outcome => panic!("Expected {expected}, not {outcome}"),
+    }
+}
+
+#[test]
+fn memory() {
+    let builder = limits(Limits {
+        max_memory_pages: Some(4),
+        ..Limits::default()
+    });
+    exceeds(
+        &builder,
+        "grow_memory",
+        LimitExceeded::Memory { max_pages: 4 },
+    );
+}
+
+#[test]
+fn table() {
+    let builder = limits(Limits {
+        max_table_elements: Some(4),
+        ..Limits::default()
+    });
+    exceeds(
+        &builder,
+        "grow_table",
+        LimitExceeded::Table { max_elements: 4 },
+    );
+}
+
+#[test]
+fn fuel() {
+    let builder = limits(Limits {
+        fuel_per_call: Some(10_000),
+        ..Limits::default()
+    });
+    exceeds(&builder, "spin", LimitExceeded::Fuel { fuel: 10_000 });
+}
+
+#[test]
+fn deadline() {
+    let deadline = Duration::from_millis(50);
+    let builder = limits(Limits {
+        call_deadline: Some(deadline),
+        ..Limits::default()
+    });
+    exceeds(&builder, "spin", LimitExceeded::Deadline { deadline });
+}
+
+#[test]
+fn within_limits() {
+    let builder = limits(Limits {
+        max_memory_pages: Some(16),
+        max_table_elements: Some(16),
+        fuel_per_call: Some(10_000),
+        call_deadline: Some(Duration::from_secs(10)),
+    });
+
+    for workflow in ["grow_memory", "grow_table"] {
+        assert!(!container(&builder, workflow).run().unwrap().is_checkpoint());
+    }
+}
+
+#[test]
+fn one_ticker_per_engine() {
+    let deadline = Duration::from_millis(200);
+    let limits = Limits {
+        call_deadline: Some(deadline),
+        ..Limits::default()
+    };
+    let engine = engine(&limits);
+    let builder = ContainerBuilder::new().engine(&engine).limits(limits);
+
+    // If each of these had its own ticker, the epoch would advance too fast,
+    // and the deadline would expire early.
+    let tickers: Vec<_> = (0..10)
+        .map(|_| EpochTicker::for_engine(&engine).unwrap())
+        .collect();
+    let start = Instant::now();
+    exceeds(&builder, "spin", LimitExceeded::Deadline { deadline });
+    assert!(start.elapsed() >= deadline);
+    drop(tickers);
+}
+
+#[test]
+fn compiled_module_deadline() {
+    let deadline = Duration::from_millis(50);
+    let limits = Limits {
+        call_deadline: Some(deadline),
+        ..Limits::default()
+    };
+    let engine = engine(&limits);
+    let module = CompiledModule::new(&engine, &wat::parse_str(GUEST).unwrap()).unwrap();
+    let mut container = ContainerBuilder::new()
+        .engine(&engine)
+        .limits(limits)
+        .build_from_module(&module)
+        .unwrap();
+    container.register_workflows().unwrap();
+    container.init_workflow("limits", "spin").unwrap();
+
+    assert!(matches!(
+        container.run().unwrap(),
+        RunOutcome::LimitExceeded(LimitExceeded::Deadline { .. })
+    ));
+}
+
+#[test]
+fn misconfigured_engine() {
+    let deadline = Limits {
+        call_deadline: Some(Duration::from_secs(1)),
+        ..Limits::default()
+    };
+    let fuel = Limits {
+        fuel_per_call: Some(10_000),
+        ..Limits::default()
+    };
+    let engine = Engine::default();
+    let module = CompiledModule::new(&engine, &wat::parse_str(GUEST).unwrap()).unwrap();
+
+    for (limits, expected) in [
+        (deadline, "need an engine with epoch interruption"),
+        (fuel, "needs an engine that consumes fuel"),
+    ] {
+        let builder = ContainerBuilder::new().engine(&engine).limits(limits);
+        let errors = [
+            builder.build_from_bytes(GUEST.as_bytes()).err(),
+            builder.build_from_module(&module).err(),
+        ];
+
+        for error in errors {
+            let error = format!("{:#}", error.expect("Expected an error"));
+            assert!(error.contains(expected), "{error}");
+        }
+    }
+
+    assert!(EpochTicker::for_engine(&engine).is_err());
+}
@@ -0,0 +1,36 @@
+;; The first build of a workflow that counts to 3, with a checkpoint after each
+;; count. `counter_v2.wat` is an upgraded build.
+;;
+;; Memory is laid out like a linker would, with static data up to `__data_end`,
+;; and the heap from `__heap_base`.
+(module
+  (import "env" "__codastraea_register_workflow_index"
+    (func $register_workflow_index (param i32 i32 i32 i32 i32)))
+
+  (memory (export "memory") 1)
+
+  ;; The number of calls to `__codastraea_run`, which is also stored in the
+  ;; heap, at `__heap_base`.
+  (global $runs (mut i32) (i32.const 0))
+  (global (export "__data_end") i32 (i32.const 64))
+  (global (export "__heap_base") i32 (i32.const 1024))
+
+  (data (i32.const 16) "migrate")
+  (data (i32.const 32) "counter")
+  (data (i32.const 48) "Original")
+
+  (func (export "__codastraea_register_workflows") (result i32)
+    (call $register_workflow_index
+      (i32.const 16) (i32.const 7)
+      (i32.const 32) (i32.const 7)
+      (i32.const 0))
+    (i32.const 1))
+
+  (func (export "__codastraea_init_workflow") (param $index i32)
+    (global.set $runs (i32.const 0))
+    (i32.store (i32.const 1024) (i32.const 0)))
+
+  (func (export "__codastraea_run") (result i32)
+    (global.set $runs (i32.add (global.get $runs) (i32.const 1)))
+    (i32.store (i32.const 1024) (global.get $runs))
+    (i32.lt_u (global.get $runs) (i32.const 3))))
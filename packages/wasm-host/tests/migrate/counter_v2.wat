@@ -0,0 +1,45 @@
+;; An upgraded build of `counter_v1.wat`, that counts to 4.
+;;
+;; Its static data is laid out the same, but the string at 48 has changed, and
+;; it has a new global.
+(module
+  (import "env" "__codastraea_register_workflow_index"
+    (func $register_workflow_index (param i32 i32 i32 i32 i32)))
+  (import "env" "__codastraea_log" (func $log (param i32 i32)))
+
+  (memory (export "memory") 1)
+
+  (global $runs (mut i32) (i32.const 0))
+  ;; Whether we've logged that this is the upgraded build.
+  (global $logged (mut i32) (i32.const 0))
+  (global (export "__data_end") i32 (i32.const 64))
+  (global (export "__heap_base") i32 (i32.const 1024))
+
+  (data (i32.const 16) "migrate")
+  (data (i32.const 32) "counter")
+  (data (i32.const 48) "Upgraded")
+
+  (func (export "__codastraea_register_workflows") (result i32)
+    (call $register_workflow_index
+      (i32.const 16) (i32.const 7)
+      (i32.const 32) (i32.const 7)
+      (i32.const 0))
+    (i32.const 1))
+
+  (func (export "__codastraea_init_workflow") (param $index i32)
+    (global.set $runs (i32.const 0))
+    (i32.store (i32.const 1024) (i32.const 0)))
+
+  (func (export "__codastraea_run") (result i32)
+    (if (i32.eqz (global.get $logged))
+      (then
+        ;; Check the static data is from this build, not the snapshot's.
+        (if (i64.ne (i64.load (i32.const 48)) (i64.const 0x6465646172677055))
+          (then (unreachable)))
+        (call $log (i32.const 48) (i32.const 8))
+        (global.set $logged (i32.const 1))))
+
+    ;; Count from the heap, to check it was migrated.
+    (global.set $runs (i32.add (i32.load (i32.const 1024)) (i32.const 1)))
+    (i32.store (i32.const 1024) (global.get $runs))
+    (i32.lt_u (global.get $runs) (i32.const 4))))
@@ -0,0 +1,120 @@
+use std::{fs, path::PathBuf};
+
+use codastraea_wasm_host::{
+    run::{RunId, RunManager, RunState},
+    runtime::ContainerBuilder,
+};
+use futures::{executor::block_on, future::join_all, StreamExt};
+
+fn counter_module() -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/counter.wat");
+    fs::read(path).unwrap()
+}
+
+fn counter() -> RunManager {
+    RunManager::new(ContainerBuilder::new(), &counter_module()).unwrap()
+}
+
+#[test]
+fn lifecycle() {
+    let runs = counter();
+    let id = runs.start("conformance", "counter").unwrap();
+    assert_eq!(runs.state(id), Some(RunState::Queued));
+
+    let paused = RunState::Paused { label: None };
+    let completed = RunState::Completed { return_value: None };
+
+    for expected in [paused.clone(), paused, completed.clone()] {
+        assert_eq!(runs.run_until_checkpoint(id).unwrap(), expected);
+        assert_eq!(runs.state(id), Some(expected));
+    }
+
+    assert_eq!(runs.remove(id), Some(completed));
+    assert!(runs.run_until_checkpoint(id).is_err());
+}
+
+#[test]
+fn watch() {
+    let runs = counter();
+    let id = runs.start("conformance", "counter").unwrap();
+    let states = runs.watch(id).unwrap();
+    runs.spawn(id).unwrap().join().unwrap();
+
+    let running = RunState::Running;
+    let paused = RunState::Paused { label: None };
+    let completed = RunState::Completed { return_value: None };
+    assert_eq!(
+        block_on(states.collect::<Vec<_>>()),
+        [
+            RunState::Queued,
+            running.clone(),
+            paused.clone(),
+            running.clone(),
+            paused,
+            running,
+            completed.clone()
+        ]
+    );
+
+    // Watching a finished run just gets its final state.
+    let states = runs.watch(id).unwrap();
+    assert_eq!(block_on(states.collect::<Vec<_>>()), [completed]);
+    assert!(runs.watch(RunId::default()).is_none());
+}
+
+#[test]
+fn concurrent_runs() {
+    let runs = counter();
+    let ids: Vec<_> = (0..4)
+        .map(|_| runs.start("conformance", "counter").unwrap())
+        .collect();
+
+    // Runs are independent, so this one stays paused while the others run.
+    runs.run_until_checkpoint(ids[0]).unwrap();
+
+    let threads: Vec<_> = ids[1..].iter().map(|&id| runs.spawn(id).unwrap()).collect();
+
+    for thread in threads {
+        assert_eq!(
+            thread.join().unwrap(),
+            RunState::Completed { return_value: None }
+        );
+    }
+
+    assert_eq!(runs.state(ids[0]), Some(RunState::Paused { label: None }));
+    assert_eq!(runs.runs().len(), 4);
+}
+
+#[test]
+fn trapped_run() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/outcome.wat");
+    let runs = RunManager::new(ContainerBuilder::new(), &fs::read(path).unwrap()).unwrap();
+    let id = runs.start("conformance", "trapped").unwrap();
+
+    let RunState::Failed { error } = runs.run_until_checkpoint(id).unwrap() else {
+        panic!("Expected the run to fail");
+    };
+    assert!(error.contains("unreachable"), "{error}");
+}
+
+#[test]
+fn unknown_workflow() {
+    assert!(counter().start("conformance", "missing").is_err());
+}
+
+#[test]
+fn async_runs() {
+    let runs = RunManager::new_async(ContainerBuilder::new(), &counter_module()).unwrap();
+
+    block_on(async {
+        let ids = join_all((0..4).map(|_| runs.start_async("conformance", "counter"))).await;
+        let ids: Vec<_> = ids.into_iter().map(Result::unwrap).collect();
+        assert!(runs.run_until_checkpoint(ids[0]).is_err());
+
+        for state in join_all(ids.iter().map(|&id| runs.run_async(id))).await {
+            assert_eq!(state.unwrap(), RunState::Completed { return_value: None });
+        }
+    });
+
+    assert!(block_on(counter().run_async(RunId::default())).is_err());
+}
@@ -0,0 +1,812 @@
+//! Snapshot and restore the hand-written guests in `tests/conformance`, and
+//! small modules that exercise the snapshot format directly.
+use std::{
+    env, fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use codastraea_server_api::{CallTreeChildNodeId, CallTreeNodeId, NodeStatus, NodeVecDiff};
+use codastraea_wasm_host::{
+    handle::{HandleId, HandleRegistry, HostHandle},
+    runtime::{Container, ContainerSnapshot, ContainerSnapshotChain},
+    snapshot::{
+        chain::SnapshotChain, read_kind, Codec, SharedMemoryPolicy, Snapshot, SnapshotKind,
+        SnapshotOptions,
+    },
+    thread::NodeStore,
+};
+use futures::{FutureExt, Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use wasmtime::{Config, Engine, ExternRef, Instance, Module, Ref, Store, Val};
+
+/// A module with a memory and a global, to snapshot without a container.
+const GUEST: &str = r#"
+    (module
+      (memory (export "memory") 1)
+      (global (export "counter") (mut i32) (i32.const 0)))
+"#;
+
+/// A module with a table of functions, to snapshot without a container.
+const TABLE: &str = r#"
+    (module
+      (memory (export "memory") 1)
+      (table (export "table") 1 funcref)
+      (func $f (export "f"))
+      (elem (i32.const 0) $f))
+"#;
+
+/// A module with two memories, to snapshot without a container.
+const MEMORIES: &str = r#"
+    (module
+      (memory (export "a") 2)
+      (memory (export "b") 1)
+      (global (export "counter") (mut i32) (i32.const 0)))
+"#;
+
+/// The size of a WASM page.
+const PAGE: usize = 0x10000;
+
+/// Compile `wat` to a WASM file that a container can load.
+fn wasm_file(name: &str, wat: &str) -> PathBuf {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let path = env::temp_dir().join(format!(
+        "codastraea-{}-{id}-{name}.wasm",
+        std::process::id()
+    ));
+    fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+    path
+}
+
+fn read_wat(dir: &str, name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(dir)
+        .join(format!("{name}.wat"));
+    fs::read_to_string(path).unwrap()
+}
+
+/// Compile `tests/conformance/{name}.wat` to a WASM file that a container can
+/// load.
+fn guest_path(name: &str) -> PathBuf {
+    wasm_file(name, &read_wat("conformance", name))
+}
+
+/// A container for the `guest`, with `workflow` initialized but not run.
+fn new_container(guest: &str, workflow: &str) -> Container {
+    let mut container = Container::from_file(&guest_path(guest)).unwrap();
+    container.register_workflows().unwrap();
+    container.init_workflow("conformance", workflow).unwrap();
+    container
+}
+
+/// A `counter` container, stopped at its first checkpoint.
+fn counter() -> Container {
+    let mut container = new_container("counter", "counter");
+    assert!(container.run().unwrap().is_checkpoint());
+    container
+}
+
+/// Run until the workflow is complete, and return the number of checkpoints.
+fn run_to_completion(container: &mut Container) -> usize {
+    let mut checkpoints = 0;
+
+    while container.run().unwrap().is_checkpoint() {
+        checkpoints += 1;
+    }
+
+    checkpoints
+}
+
+/// The diffs `stream` has ready.
+fn ready_diffs(stream: &mut (impl Stream<Item = NodeVecDiff> + Unpin)) -> Vec<NodeVecDiff> {
+    let mut diffs = Vec::new();
+
+    while let Some(Some(diff)) = stream.next().now_or_never() {
+        diffs.push(diff);
+    }
+
+    diffs
+}
+
+/// The ids and statuses of the nodes in `id`.
+fn nodes(node_store: &NodeStore, id: CallTreeNodeId) -> Vec<(CallTreeChildNodeId, NodeStatus)> {
+    match ready_diffs(&mut node_store.watch(id)).as_slice() {
+        [NodeVecDiff::Replace(nodes)] => nodes.iter().map(|node| (node.id, node.status)).collect(),
+        [] => Vec::new(),
+        diffs => panic!("Expected the current nodes, not {diffs:?}"),
+    }
+}
+
+fn instantiate(wat: &str) -> (Store<()>, Instance) {
+    let engine = Engine::default();
+    let module = Module::new(&engine, wat).unwrap();
+    let mut store = Store::new(&engine, ());
+    let instance = Instance::new(&mut store, &module, &[]).unwrap();
+    (store, instance)
+}
+
+fn memory<'a>(store: &'a mut Store<()>, instance: &Instance, name: &str) -> &'a mut [u8] {
+    instance
+        .get_memory(&mut *store, name)
+        .unwrap()
+        .data_mut(store)
+}
+
+/// Fill `data` with bytes that don't compress.
+fn fill_random(data: &mut [u8]) {
+    let mut state = 0x2545_f491_u32;
+
+    for byte in data {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        *byte = state as u8;
+    }
+}
+
+/// Set the guest's memory and global to `value`.
+fn set_state(store: &mut Store<()>, instance: &Instance, value: u8) {
+    memory(store, instance, "memory")[0] = value;
+    instance
+        .get_global(&mut *store, "counter")
+        .unwrap()
+        .set(&mut *store, Val::I32(value.into()))
+        .unwrap();
+}
+
+fn state(store: &mut Store<()>, instance: &Instance) -> (u8, i32) {
+    let counter = instance
+        .get_global(&mut *store, "counter")
+        .unwrap()
+        .get(&mut *store)
+        .unwrap_i32();
+    (memory(store, instance, "memory")[0], counter)
+}
+
+fn write(snapshot: &Snapshot) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    snapshot.write_to(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn persist_round_trip() {
+    let (mut store, instance) = instantiate(GUEST);
+    set_state(&mut store, &instance, 1);
+    let bytes = write(&Snapshot::new(&mut store, &instance).unwrap());
+
+    let snapshot = Snapshot::read_from(bytes.as_slice()).unwrap();
+    // The format is deterministic, so writing it again gives the same bytes.
+    assert_eq!(write(&snapshot), bytes);
+
+    set_state(&mut store, &instance, 2);
+    snapshot.restore(&mut store, &instance).unwrap();
+    assert_eq!(state(&mut store, &instance), (1, 1));
+}
+
+#[test]
+fn persist_rejects_invalid_data() {
+    let (mut store, instance) = instantiate(GUEST);
+    let bytes = write(&Snapshot::new(&mut store, &instance).unwrap());
+
+    let error = Snapshot::read_from(&b"not a snapshot"[..]).err().unwrap();
+    assert_eq!(error.to_string(), "Not a codastraea snapshot");
+
+    let truncated = &bytes[..bytes.len() / 2];
+    assert!(Snapshot::read_from(truncated).is_err());
+
+    let error = ContainerSnapshot::read_from(bytes.as_slice())
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Expected a Container snapshot, but found a Wasm snapshot"
+    );
+}
+
+#[test]
+fn restore_keeps_node_ids() {
+    let mut container = new_container("trace", "traced");
+    let node_store = container.node_store();
+    assert!(container.run().unwrap().is_checkpoint());
+    let root = nodes(&node_store, CallTreeNodeId::Root);
+    let traced = CallTreeNodeId::Child(root[0].0);
+    let children = nodes(&node_store, traced);
+    let mut watch_children = node_store.watch(traced);
+    ready_diffs(&mut watch_children);
+    let mut bytes = Vec::new();
+    container.snapshot().unwrap().write_to(&mut bytes).unwrap();
+
+    assert!(!container.run().unwrap().is_checkpoint());
+    assert_eq!(nodes(&node_store, traced).len(), 3);
+    ready_diffs(&mut watch_children);
+
+    let snapshot = ContainerSnapshot::read_from(bytes.as_slice()).unwrap();
+    container.restore(&snapshot).unwrap();
+    assert_eq!(nodes(&node_store, CallTreeNodeId::Root), root);
+
+    // Watchers of restored nodes see the restored children.
+    let diffs = ready_diffs(&mut watch_children);
+    let [NodeVecDiff::Replace(restored)] = diffs.as_slice() else {
+        panic!("Expected the children to be replaced");
+    };
+    let restored: Vec<_> = restored.iter().map(|node| (node.id, node.status)).collect();
+    assert_eq!(restored, children);
+
+    // Restoring into a new container gives the same ids too.
+    let mut container = new_container("trace", "traced");
+    container.restore(&snapshot).unwrap();
+    let node_store = container.node_store();
+    assert_eq!(nodes(&node_store, CallTreeNodeId::Root), root);
+    assert_eq!(nodes(&node_store, traced), children);
+    assert!(!container.run().unwrap().is_checkpoint());
+}
+
+#[test]
+fn restore_ends_removed_node_watchers() {
+    let mut container = new_container("trace", "traced");
+    let node_store = container.node_store();
+    let snapshot = container.snapshot().unwrap();
+    assert!(container.run().unwrap().is_checkpoint());
+    let root = nodes(&node_store, CallTreeNodeId::Root);
+    let mut watch_traced = node_store.watch(CallTreeNodeId::Child(root[0].0));
+    ready_diffs(&mut watch_traced);
+
+    container.restore(&snapshot).unwrap();
+    assert!(nodes(&node_store, CallTreeNodeId::Root).is_empty());
+    assert!(matches!(watch_traced.next().now_or_never(), Some(None)));
+}
+
+#[test]
+fn chain_restores_each_snapshot() {
+    let (mut store, instance) = instantiate(MEMORIES);
+    let mut chain = SnapshotChain::new(&mut store, &instance).unwrap();
+    let mut expected = vec![memory(&mut store, &instance, "a").to_vec()];
+
+    for (index, page) in [0, 1, 0].into_iter().enumerate() {
+        memory(&mut store, &instance, "a")[page * PAGE] = index as u8 + 1;
+        chain.push(&mut store, &instance).unwrap();
+        expected.push(memory(&mut store, &instance, "a").to_vec());
+    }
+
+    let mut bytes = Vec::new();
+    chain.write_to(&mut bytes).unwrap();
+    let chain = SnapshotChain::read_from(bytes.as_slice()).unwrap();
+    assert_eq!(chain.len(), 4);
+
+    for (index, expected) in expected.iter().enumerate() {
+        chain
+            .get(index)
+            .unwrap()
+            .restore(&mut store, &instance)
+            .unwrap();
+        assert_eq!(
+            memory(&mut store, &instance, "a"),
+            expected,
+            "snapshot {index}"
+        );
+    }
+
+    assert!(chain.get(4).is_err());
+}
+
+#[test]
+fn chain_only_stores_changed_pages() {
+    let (mut store, instance) = instantiate(MEMORIES);
+    fill_random(memory(&mut store, &instance, "a"));
+    let mut chain = SnapshotChain::new(&mut store, &instance).unwrap();
+    let chain_len = |chain: &SnapshotChain| {
+        let mut bytes = Vec::new();
+        chain.write_to(&mut bytes).unwrap();
+        bytes.len()
+    };
+    let base_len = chain_len(&chain);
+
+    memory(&mut store, &instance, "a")[PAGE] ^= 1;
+    chain.push(&mut store, &instance).unwrap();
+    let delta_len = chain_len(&chain) - base_len;
+    assert!(delta_len > PAGE && delta_len < 2 * PAGE, "{delta_len}");
+}
+
+#[test]
+fn failed_chain_push_leaves_chain_unchanged() {
+    let (mut store, instance) = instantiate(MEMORIES);
+    let mut chain = SnapshotChain::new(&mut store, &instance).unwrap();
+
+    // Memory `a` matches, but `b` is missing.
+    let (mut other_store, other) = instantiate(r#"(module (memory (export "a") 2))"#);
+    memory(&mut other_store, &other, "a").fill(1);
+    assert!(chain.push(&mut other_store, &other).is_err());
+    assert_eq!(chain.len(), 1);
+
+    memory(&mut store, &instance, "a").fill(1);
+    chain.push(&mut store, &instance).unwrap();
+    memory(&mut store, &instance, "a").fill(0);
+    chain
+        .get(1)
+        .unwrap()
+        .restore(&mut store, &instance)
+        .unwrap();
+    assert!(memory(&mut store, &instance, "a")
+        .iter()
+        .all(|&byte| byte == 1));
+}
+
+#[test]
+fn container_chain() {
+    let mut container = counter();
+    let mut chain = container.snapshot_chain().unwrap();
+    assert!(container.run().unwrap().is_checkpoint());
+    container.push_snapshot(&mut chain).unwrap();
+
+    let mut bytes = Vec::new();
+    chain.write_to(&mut bytes).unwrap();
+    let chain = ContainerSnapshotChain::read_from(bytes.as_slice()).unwrap();
+    assert_eq!(chain.len(), 2);
+    assert_eq!(run_to_completion(&mut container), 0);
+
+    for (index, checkpoints) in [(0, 1), (1, 0)] {
+        container.restore(&chain.get(index).unwrap()).unwrap();
+        assert_eq!(run_to_completion(&mut container), checkpoints);
+    }
+}
+
+#[test]
+fn fingerprint_mismatch() {
+    let snapshot = counter().snapshot().unwrap();
+    let mut chain = counter().snapshot_chain().unwrap();
+    let mut other = new_container("trace", "traced");
+    assert_ne!(snapshot.fingerprint(), &other.fingerprint());
+
+    let error = other.restore(&snapshot).unwrap_err().to_string();
+    assert!(
+        error.starts_with("Snapshot was taken from a different module"),
+        "{error}"
+    );
+    assert!(other.push_snapshot(&mut chain).is_err());
+    assert_eq!(chain.len(), 1);
+
+    // The fingerprint survives persisting the snapshot.
+    let mut container = counter();
+    let mut bytes = Vec::new();
+    snapshot.write_to(&mut bytes).unwrap();
+    let snapshot = ContainerSnapshot::read_from(bytes.as_slice()).unwrap();
+    assert_eq!(snapshot.fingerprint(), &container.fingerprint());
+    container.restore(&snapshot).unwrap();
+}
+
+fn verified_snapshot(store: &mut Store<()>, instance: &Instance) -> Snapshot {
+    let options = SnapshotOptions {
+        verify: true,
+        ..SnapshotOptions::default()
+    };
+    Snapshot::with_options(store, instance, &options).unwrap()
+}
+
+#[test]
+fn verify_restore() {
+    let (mut store, instance) = instantiate(MEMORIES);
+    memory(&mut store, &instance, "a")[..PAGE].fill(0x5a);
+    let snapshot = verified_snapshot(&mut store, &instance);
+    let digest = Sha256::digest(memory(&mut store, &instance, "a"));
+    memory(&mut store, &instance, "a").fill(1);
+    snapshot.restore(&mut store, &instance).unwrap();
+
+    // Corrupt the memory digest in the persisted snapshot.
+    let mut bytes = Vec::new();
+    snapshot.write_to(&mut bytes).unwrap();
+    let digest = bytes
+        .windows(digest.len())
+        .position(|window| window == digest.as_slice())
+        .unwrap();
+    bytes[digest] ^= 1;
+    let corrupt = Snapshot::read_from(bytes.as_slice()).unwrap();
+    let error = corrupt.restore(&mut store, &instance).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Restored state doesn't match the snapshot:\nMemory 'a' has different contents"
+    );
+}
+
+#[test]
+fn verify_reports_extra_items() {
+    let (mut store, instance) = instantiate(MEMORIES);
+    let snapshot = verified_snapshot(&mut store, &instance);
+    let (mut store, instance) = instantiate(
+        r#"
+        (module
+          (memory (export "a") 2)
+          (memory (export "b") 1)
+          (global (export "counter") (mut i32) (i32.const 0))
+          (global (export "extra") (mut i32) (i32.const 0)))
+        "#,
+    );
+    let error = snapshot.restore(&mut store, &instance).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Restored state doesn't match the snapshot:\nGlobal 'extra' is not in the snapshot"
+    );
+}
+
+#[test]
+fn verify_restore_into_grown_memory() {
+    let (mut store, instance) = instantiate(TABLE);
+    memory(&mut store, &instance, "memory").fill(0x5a);
+    let snapshot = verified_snapshot(&mut store, &instance);
+
+    // Memories can't shrink, so the restored memory keeps the extra page.
+    let grown = instance.get_memory(&mut store, "memory").unwrap();
+    grown.grow(&mut store, 1).unwrap();
+    grown.data_mut(&mut store).fill(1);
+    snapshot.restore(&mut store, &instance).unwrap();
+
+    let data = grown.data(&store);
+    assert_eq!(data.len(), 2 * PAGE);
+    assert!(data[..PAGE].iter().all(|&byte| byte == 0x5a));
+    assert!(data[PAGE..].iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn restore_clears_grown_table() {
+    let (mut store, instance) = instantiate(TABLE);
+    let snapshot = verified_snapshot(&mut store, &instance);
+
+    // Tables can't shrink either, so the restore must null the new entries.
+    let table = instance.get_table(&mut store, "table").unwrap();
+    let func = instance.get_func(&mut store, "f").unwrap();
+    table.grow(&mut store, 2, Ref::Func(Some(func))).unwrap();
+    snapshot.restore(&mut store, &instance).unwrap();
+
+    assert_eq!(table.size(&store), 3);
+    assert!(table.get(&mut store, 0).unwrap().unwrap_func().is_some());
+
+    for index in 1..3 {
+        assert!(table
+            .get(&mut store, index)
+            .unwrap()
+            .unwrap_func()
+            .is_none());
+    }
+}
+
+#[test]
+fn codecs() {
+    let (mut store, instance) = instantiate(MEMORIES);
+
+    for (index, byte) in memory(&mut store, &instance, "a").iter_mut().enumerate() {
+        *byte = (index % 7) as u8;
+    }
+
+    let expected = memory(&mut store, &instance, "a").to_vec();
+
+    for codec in [Codec::None, Codec::Deflate { level: 9 }, Codec::Lz4] {
+        let options = SnapshotOptions {
+            codec,
+            ..SnapshotOptions::default()
+        };
+        let mut bytes = Vec::new();
+        Snapshot::with_options(&mut store, &instance, &options)
+            .unwrap()
+            .write_to(&mut bytes)
+            .unwrap();
+
+        if codec != Codec::None {
+            assert!(bytes.len() < expected.len() / 2, "{codec:?}");
+        }
+
+        // The codec is read from the snapshot.
+        memory(&mut store, &instance, "a").fill(0);
+        Snapshot::read_from(bytes.as_slice())
+            .unwrap()
+            .restore(&mut store, &instance)
+            .unwrap();
+        assert_eq!(memory(&mut store, &instance, "a"), expected, "{codec:?}");
+    }
+
+    let options = SnapshotOptions {
+        codec: Codec::Deflate { level: 10 },
+        ..SnapshotOptions::default()
+    };
+    assert!(Snapshot::with_options(&mut store, &instance, &options).is_err());
+}
+
+#[test]
+fn zero_ranges() {
+    let (mut store, instance) = instantiate(MEMORIES);
+    let options = SnapshotOptions {
+        codec: Codec::None,
+        ..SnapshotOptions::default()
+    };
+    let snapshot_len = |store: &mut Store<()>| {
+        let mut bytes = Vec::new();
+        Snapshot::with_options(store, &instance, &options)
+            .unwrap()
+            .write_to(&mut bytes)
+            .unwrap();
+        bytes.len()
+    };
+
+    // Zeroed memory isn't stored.
+    let empty_len = snapshot_len(&mut store);
+    assert!(empty_len < 1024, "{empty_len}");
+
+    // Only the non-zero block is stored.
+    memory(&mut store, &instance, "a")[PAGE + 1] = 1;
+    let snapshot = Snapshot::with_options(&mut store, &instance, &options).unwrap();
+    let sparse_len = snapshot_len(&mut store);
+    assert!(sparse_len - empty_len <= 4096 + 64, "{sparse_len}");
+
+    // Restoring zeroes anything written since the snapshot, either side of
+    // the non-zero block.
+    let a = memory(&mut store, &instance, "a");
+    a[0] = 2;
+    a[PAGE + 1] = 3;
+    a[2 * PAGE - 1] = 4;
+    snapshot.restore(&mut store, &instance).unwrap();
+    let a = memory(&mut store, &instance, "a");
+    assert_eq!((a[0], a[PAGE + 1], a[2 * PAGE - 1]), (0, 1, 0));
+    assert_eq!(a.iter().filter(|&&byte| byte != 0).count(), 1);
+}
+
+#[test]
+fn invalid_zero_range() {
+    let (mut store, instance) = instantiate(MEMORIES);
+    memory(&mut store, &instance, "a")[PAGE] = 1;
+    let mut bytes = Vec::new();
+    Snapshot::new(&mut store, &instance)
+        .unwrap()
+        .write_to(&mut bytes)
+        .unwrap();
+
+    // Swap the start and end of the zero range after the non-zero block.
+    let encode = |start: usize, end: usize| {
+        [start as u64, end as u64]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<u8>>()
+    };
+    let range = encode(PAGE + 4096, 2 * PAGE);
+    let index = bytes
+        .windows(range.len())
+        .position(|window| window == range)
+        .unwrap();
+    bytes[index..index + range.len()].copy_from_slice(&encode(2 * PAGE, PAGE + 4096));
+
+    let snapshot = Snapshot::read_from(bytes.as_slice()).unwrap();
+    let error = snapshot.restore(&mut store, &instance).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid zero range");
+}
+
+const EXTERN_REFS: &str = r#"
+    (module
+      (global (export "handle") (mut externref) (ref.null extern))
+      (table (export "handles") 2 externref))
+"#;
+
+#[test]
+fn handle_registry() {
+    let (mut store, instance) = instantiate(EXTERN_REFS);
+    let handle = HostHandle::new(HandleId::new("file", 7), "original".to_string());
+    let extern_ref = handle.into_extern_ref(&mut store).unwrap();
+    let global = instance.get_global(&mut store, "handle").unwrap();
+    let table = instance.get_table(&mut store, "handles").unwrap();
+    global
+        .set(&mut store, Val::ExternRef(Some(extern_ref)))
+        .unwrap();
+    table
+        .set(&mut store, 1, Ref::Extern(Some(extern_ref)))
+        .unwrap();
+    let snapshot = Snapshot::new(&mut store, &instance).unwrap();
+    global.set(&mut store, Val::ExternRef(None)).unwrap();
+    table.set(&mut store, 1, Ref::Extern(None)).unwrap();
+
+    let error = snapshot.restore(&mut store, &instance).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "No factory registered for handle kind 'file'"
+    );
+
+    let recreated = Arc::new(AtomicUsize::new(0));
+    let mut handles = HandleRegistry::default();
+    handles.register("file", {
+        let recreated = recreated.clone();
+        move |id| {
+            recreated.fetch_add(1, Ordering::Relaxed);
+            Ok(format!("recreated {id}"))
+        }
+    });
+    snapshot
+        .restore_with_handles(&mut store, &instance, &handles)
+        .unwrap();
+    // The handle is only recreated once, even though there are 2 references.
+    assert_eq!(recreated.load(Ordering::Relaxed), 1);
+
+    let Val::ExternRef(Some(extern_ref)) = global.get(&mut store) else {
+        panic!("Expected a handle");
+    };
+    let handle = HostHandle::from_extern_ref(&mut store, &extern_ref).unwrap();
+    assert_eq!(handle.id(), &HandleId::new("file", 7));
+    assert_eq!(handle.resource::<String>().unwrap(), "recreated 7");
+    assert!(table.get(&mut store, 0).unwrap().unwrap_extern().is_none());
+    assert!(table.get(&mut store, 1).unwrap().unwrap_extern().is_some());
+}
+
+#[test]
+fn unsupported_refs() {
+    let (mut store, instance) = instantiate(EXTERN_REFS);
+    let extern_ref = ExternRef::new(&mut store, "not a handle").unwrap();
+    let global = instance.get_global(&mut store, "handle").unwrap();
+    global
+        .set(&mut store, Val::ExternRef(Some(extern_ref)))
+        .unwrap();
+    let error = Snapshot::new(&mut store, &instance).err().unwrap();
+    assert_eq!(
+        format!("{error:#}"),
+        "Global 'handle': `ExternRef` is not a `HostHandle`"
+    );
+
+    // Non-null `AnyRef`s need the GC proposal, which the host doesn't enable.
+    let mut config = Config::new();
+    config.wasm_gc(true);
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::new(
+        &engine,
+        r#"
+        (module
+          (global (export "null") (mut anyref) (ref.null any))
+          (global (export "i31") (mut anyref) (ref.i31 (i32.const 5))))
+        "#,
+    )
+    .unwrap();
+    let mut store = Store::new(&engine, ());
+    let instance = Instance::new(&mut store, &module, &[]).unwrap();
+    let error = Snapshot::new(&mut store, &instance).err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Global 'i31': Non-null `AnyRef`s are not supported"
+    );
+}
+
+#[test]
+fn shared_memory_policy() {
+    let (mut store, instance) = instantiate(r#"(module (memory (export "m") 1 1 shared))"#);
+    let error = Snapshot::new(&mut store, &instance).err().unwrap();
+    assert!(
+        format!("{error:#}").contains("Memory 'm' is shared"),
+        "{error:#}"
+    );
+
+    let options = SnapshotOptions {
+        shared_memory: SharedMemoryPolicy::Copy,
+        ..SnapshotOptions::default()
+    };
+    let snapshot = Snapshot::with_options(&mut store, &instance, &options).unwrap();
+    assert!(snapshot.summary().to_string().contains("m:"));
+    let error = snapshot.restore(&mut store, &instance).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Memory 'm' is shared, and restoring shared memories isn't supported, as other threads \
+         could be using them"
+    );
+}
+
+#[test]
+fn inspect_and_diff() {
+    let (mut store, instance) = instantiate(MEMORIES);
+    let old = Snapshot::new(&mut store, &instance).unwrap();
+    assert!(old.diff(&old).unwrap().is_empty());
+
+    let counter = instance.get_global(&mut store, "counter").unwrap();
+    counter.set(&mut store, Val::I32(3)).unwrap();
+    memory(&mut store, &instance, "a")[PAGE] = 1;
+    memory(&mut store, &instance, "b")[0] = 1;
+    let new = Snapshot::new(&mut store, &instance).unwrap();
+
+    let summary = new.summary().to_string();
+    assert!(summary.contains("counter = i32 3"), "{summary}");
+    assert!(summary.contains("a: page size 65536, 131072 bytes uncompressed"));
+    assert_eq!(
+        old.diff(&new).unwrap().to_string(),
+        "Globals:\n    counter: i32 0 -> i32 3\nMemories:\n    a: page 1 differs\n    b: page 0 \
+         differs\n"
+    );
+}
+
+#[test]
+fn read_kind_from_header() {
+    let mut container = counter();
+    let mut wasm = Vec::new();
+    container
+        .snapshot()
+        .unwrap()
+        .wasm()
+        .write_to(&mut wasm)
+        .unwrap();
+    let mut container_snapshot = Vec::new();
+    container
+        .snapshot()
+        .unwrap()
+        .write_to(&mut container_snapshot)
+        .unwrap();
+    let mut chain = Vec::new();
+    container
+        .snapshot_chain()
+        .unwrap()
+        .write_to(&mut chain)
+        .unwrap();
+    let (mut store, instance) = instantiate(MEMORIES);
+    let mut wasm_chain = Vec::new();
+    SnapshotChain::new(&mut store, &instance)
+        .unwrap()
+        .write_to(&mut wasm_chain)
+        .unwrap();
+
+    for (bytes, kind) in [
+        (wasm, SnapshotKind::Wasm),
+        (container_snapshot, SnapshotKind::Container),
+        (wasm_chain, SnapshotKind::Chain),
+        (chain, SnapshotKind::ContainerChain),
+    ] {
+        assert_eq!(read_kind(bytes.as_slice()).unwrap(), kind);
+    }
+}
+
+/// A build of the `migrate::counter` workflow, stopped at its first checkpoint.
+fn counter_build(build: &str, wat: &str) -> Container {
+    let mut container = Container::from_file(&wasm_file(build, wat)).unwrap();
+    container.register_workflows().unwrap();
+    container.init_workflow("migrate", "counter").unwrap();
+    assert!(container.run().unwrap().is_checkpoint());
+    container
+}
+
+#[test]
+fn migrate_to_upgraded_build() {
+    let mut v1 = counter_build("counter_v1", &read_wat("migrate", "counter_v1"));
+    let mut bytes = Vec::new();
+    v1.snapshot().unwrap().write_to(&mut bytes).unwrap();
+    let snapshot = ContainerSnapshot::read_from(bytes.as_slice()).unwrap();
+    assert_eq!(run_to_completion(&mut v1), 1);
+
+    let mut v2 = counter_build("counter_v2", &read_wat("migrate", "counter_v2"));
+    assert!(v2.restore(&snapshot).is_err());
+    let report = v2.migrate(&snapshot).unwrap();
+    assert!(report.mismatches.is_empty());
+    assert_eq!(
+        report.warnings,
+        [
+            "Static data at 0x30 in memory 'memory' differs from the snapshot, so it's reset to \
+             this build's data",
+            "Global '__codastraea_global_name_logged' isn't in the snapshot, so it keeps its \
+             initial value"
+        ]
+    );
+
+    // The upgraded build counts to 4, carrying on from the snapshot. It traps
+    // if the string it logs is the snapshot's static data, rather than its own.
+    assert_eq!(run_to_completion(&mut v2), 2);
+}
+
+#[test]
+fn migrate_rejects_moved_static_data() {
+    let snapshot = counter_build("counter_v1", &read_wat("migrate", "counter_v1"))
+        .snapshot()
+        .unwrap();
+    let v2 = read_wat("migrate", "counter_v2").replace(
+        r#"(global (export "__data_end") i32 (i32.const 64))"#,
+        r#"(global (export "__data_end") i32 (i32.const 2048))"#,
+    );
+    let mut v2 = counter_build("counter_v2", &v2);
+
+    let report = v2.check_migration(&snapshot);
+    assert_eq!(
+        report.mismatches,
+        ["Memory layout: '__data_end' moved from 0x40 to 0x800"]
+    );
+    assert!(v2.migrate(&snapshot).is_err());
+}
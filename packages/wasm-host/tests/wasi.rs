@@ -0,0 +1,166 @@
+//! Run guests that use WASI, in a fresh workspace directory for each test.
+use std::{fs, path::PathBuf};
+
+use codastraea_wasm_host::{
+    runtime::{Container, ContainerBuilder},
+    wasi::WasiOptions,
+};
+
+/// Workflows that use WASI, and write what they see to stdout.
+///
+/// - `replay` reads the wall clock, the monotonic clock and 8 random bytes.
+/// - `overflow` writes the first 40 bytes of memory, twice.
+/// - `open_inside` and `open_outside` try to open `inside.txt` and
+///   `../outside.txt` in the workspace, and write the errno as an `i32`.
+const GUEST: &str = r#"
+    (module
+      (import "codastraea" "__codastraea_register_workflow_index"
+        (func $register_workflow_index (param i32 i32 i32 i32 i32)))
+      (import "wasi_snapshot_preview1" "clock_time_get"
+        (func $clock_time_get (param i32 i64 i32) (result i32)))
+      (import "wasi_snapshot_preview1" "random_get"
+        (func $random_get (param i32 i32) (result i32)))
+      (import "wasi_snapshot_preview1" "fd_write"
+        (func $fd_write (param i32 i32 i32 i32) (result i32)))
+      (import "wasi_snapshot_preview1" "path_open"
+        (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+
+      (memory (export "memory") 1)
+      (global $workflow (mut i32) (i32.const 0))
+
+      (data (i32.const 0) "wasireplayoverflowopen_insideopen_outside")
+      (data (i32.const 64) "inside.txt")
+      (data (i32.const 80) "../outside.txt")
+
+      (func (export "__codastraea_register_workflows") (result i32)
+        (call $register_workflow_index (i32.const 0) (i32.const 4) (i32.const 4) (i32.const 6)
+          (i32.const 0))
+        (call $register_workflow_index (i32.const 0) (i32.const 4) (i32.const 10) (i32.const 8)
+          (i32.const 1))
+        (call $register_workflow_index (i32.const 0) (i32.const 4) (i32.const 18) (i32.const 11)
+          (i32.const 2))
+        (call $register_workflow_index (i32.const 0) (i32.const 4) (i32.const 29) (i32.const 12)
+          (i32.const 3))
+        (i32.const 4))
+
+      (func (export "__codastraea_init_workflow") (param $index i32)
+        (global.set $workflow (local.get $index)))
+
+      ;; Write `len` bytes at `ptr` to stdout, ignoring errors.
+      (func $write (param $ptr i32) (param $len i32)
+        (i32.store (i32.const 256) (local.get $ptr))
+        (i32.store (i32.const 260) (local.get $len))
+        (drop (call $fd_write (i32.const 1) (i32.const 256) (i32.const 1) (i32.const 264))))
+
+      ;; Open `len` bytes of path at `ptr`, in the workspace, and write the errno.
+      (func $open (param $ptr i32) (param $len i32)
+        (i32.store (i32.const 512)
+          (call $path_open (i32.const 3) (i32.const 0) (local.get $ptr) (local.get $len)
+            (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 516)))
+        (call $write (i32.const 512) (i32.const 4)))
+
+      (func (export "__codastraea_run") (result i32)
+        (block $open_outside
+          (block $open_inside
+            (block $overflow
+              (block $replay
+                (br_table $replay $overflow $open_inside $open_outside
+                  (global.get $workflow)))
+              (drop (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 512)))
+              (drop (call $clock_time_get (i32.const 1) (i64.const 1) (i32.const 520)))
+              (drop (call $random_get (i32.const 528) (i32.const 8)))
+              (call $write (i32.const 512) (i32.const 24))
+              (return (i32.const 0)))
+            (call $write (i32.const 0) (i32.const 40))
+            (call $write (i32.const 0) (i32.const 40))
+            (return (i32.const 0)))
+          (call $open (i32.const 64) (i32.const 10))
+          (return (i32.const 0)))
+        (call $open (i32.const 80) (i32.const 14))
+        (i32.const 0)))
+"#;
+
+/// An empty directory, unique to `test`.
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("codastraea-wasi-{test}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn container(wasi: WasiOptions, workflow: &str) -> Container {
+    let mut container = ContainerBuilder::new()
+        .wasi(wasi)
+        .build_from_bytes(GUEST.as_bytes())
+        .unwrap();
+    container.register_workflows().unwrap();
+    container.init_workflow("wasi", workflow).unwrap();
+    container
+}
+
+#[test]
+fn replay_after_restore() {
+    let mut container = container(WasiOptions::default(), "replay");
+    let snapshot = container.snapshot().unwrap();
+
+    assert!(!container.run().unwrap().is_checkpoint());
+    let first = container.stdout().unwrap();
+    assert_eq!(first.len(), 24);
+
+    container.restore(&snapshot).unwrap();
+    assert!(!container.run().unwrap().is_checkpoint());
+    let stdout = container.stdout().unwrap();
+    assert_eq!(stdout[24..], first, "Expected the recorded values");
+}
+
+#[test]
+fn output_capacity() {
+    let wasi = WasiOptions {
+        output_capacity: 16,
+        ..WasiOptions::default()
+    };
+    let mut container = container(wasi, "overflow");
+
+    assert!(!container.run().unwrap().is_checkpoint());
+    assert_eq!(container.stdout().unwrap(), b"wasireplayoverfl");
+}
+
+#[test]
+fn workspace_per_container() {
+    let dir = temp_dir("workspace");
+    let wasi = WasiOptions {
+        workspaces: Some(dir.join("workspaces")),
+        ..WasiOptions::default()
+    };
+    let first = container(wasi.clone(), "open_inside");
+    let second = container(wasi, "open_inside");
+    let workspace = first.workspace().unwrap();
+
+    assert_eq!(workspace.parent(), Some(dir.join("workspaces").as_path()));
+    assert!(workspace.is_dir());
+    assert_ne!(second.workspace(), Some(workspace));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn workspace_confinement() {
+    let dir = temp_dir("confinement");
+    let wasi = WasiOptions {
+        workspaces: Some(dir.clone()),
+        ..WasiOptions::default()
+    };
+    let errno = |workflow| {
+        let mut container = container(wasi.clone(), workflow);
+        let workspace = container.workspace().unwrap();
+        fs::write(workspace.join("inside.txt"), b"inside").unwrap();
+        fs::write(workspace.join("../outside.txt"), b"outside").unwrap();
+        assert!(!container.run().unwrap().is_checkpoint());
+        i32::from_le_bytes(container.stdout().unwrap().try_into().unwrap())
+    };
+
+    assert_eq!(errno("open_inside"), 0);
+    assert_ne!(errno("open_outside"), 0);
+
+    fs::remove_dir_all(dir).unwrap();
+}